@@ -0,0 +1,170 @@
+//! Records per second of the reader, the engine and the writer
+use bank::{
+    generator::{write_rows, Format, Generator, GeneratorConfig},
+    input_file_reader::{InputFileReader, InputFileRecord, InputFileRecordType},
+    output_record::OutputRecord,
+    output_writer::OutputWriter,
+    service::{Service, TransactionRecordHandler},
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use std::{hint::black_box, io};
+use tokio::runtime::{Builder, Runtime};
+
+const CLIENTS: u16 = 1_000;
+const RECORDS: u64 = 10_000;
+
+/// Name, records applied before measuring, measured records
+type Case<'a> = (&'a str, Vec<&'a [InputFileRecord]>, &'a [InputFileRecord]);
+
+fn generated_csv(rows: u64, dispute_rate: f64, chargeback_rate: f64) -> Vec<u8> {
+    let generator = Generator::new(GeneratorConfig {
+        clients: CLIENTS,
+        transactions: rows,
+        withdrawal_ratio: 0.3,
+        dispute_rate,
+        chargeback_rate,
+        malformed_rate: 0.0,
+        seed: 42,
+    })
+    .expect("generator config should be valid");
+    let mut output = Vec::new();
+    write_rows(&mut output, Format::Csv, generator).expect("rows should be written");
+    output
+}
+
+fn runtime() -> Runtime {
+    Builder::new_current_thread()
+        .build()
+        .expect("runtime should start")
+}
+
+fn apply(runtime: &Runtime, service: &mut Service, records: &[InputFileRecord]) {
+    runtime.block_on(async {
+        for record in records {
+            let _ = black_box(service.handle(record).await);
+        }
+    });
+}
+
+/// One record of the given type for each of the first `RECORDS` transactions, each of its own
+/// client, so that a chargeback locks no client the next ones refer to
+fn records(r#type: InputFileRecordType, amount: Option<f64>) -> Vec<InputFileRecord> {
+    (1..=RECORDS)
+        .map(|tx| InputFileRecord {
+            r#type,
+            client: tx,
+            tx,
+            amount,
+            timestamp: None,
+            reason: None,
+        })
+        .collect()
+}
+
+fn csv_parsing(c: &mut Criterion) {
+    let input = generated_csv(100_000, 0.02, 0.2);
+    let mut group = c.benchmark_group("csv_parsing");
+    group.throughput(Throughput::Elements(100_000));
+    group.bench_function("parse", |b| {
+        b.iter(|| InputFileReader::parse(black_box(input.as_slice())))
+    });
+    group.bench_function("parse_strict", |b| {
+        b.iter(|| InputFileReader::parse_strict(black_box(input.as_slice())))
+    });
+    group.finish();
+}
+
+fn handle_per_type(c: &mut Criterion) {
+    let runtime = runtime();
+    let deposits = records(InputFileRecordType::Deposit, Some(100.0));
+    let withdrawals = records(InputFileRecordType::Withdrawal, Some(1.0))
+        .into_iter()
+        .map(|record| InputFileRecord {
+            tx: record.tx + RECORDS,
+            ..record
+        })
+        .collect::<Vec<_>>();
+    let disputes = records(InputFileRecordType::Dispute, None);
+    let resolves = records(InputFileRecordType::Resolve, None);
+    let chargebacks = records(InputFileRecordType::Chargeback, None);
+    // each type is measured on the state the records before it in the lifecycle leave behind
+    let cases: [Case; 5] = [
+        ("deposit", vec![], &deposits),
+        ("withdrawal", vec![&deposits], &withdrawals),
+        ("dispute", vec![&deposits], &disputes),
+        ("resolve", vec![&deposits, &disputes], &resolves),
+        (
+            "chargeback",
+            vec![&deposits, &disputes, &resolves],
+            &chargebacks,
+        ),
+    ];
+    let mut group = c.benchmark_group("handle");
+    group.throughput(Throughput::Elements(RECORDS));
+    for (name, setup, measured) in cases {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    let mut service = Service::new();
+                    for records in &setup {
+                        apply(&runtime, &mut service, records);
+                    }
+                    service
+                },
+                |mut service| apply(&runtime, &mut service, measured),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn workloads(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("workload");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(100_000));
+    // few chargebacks, a locked client would turn its remaining records into cheap no-ops
+    for (name, dispute_rate, chargeback_rate) in
+        [("transfers", 0.0, 0.0), ("dispute_heavy", 0.5, 0.01)]
+    {
+        let input = generated_csv(100_000, dispute_rate, chargeback_rate);
+        let records = InputFileReader::parse(input.as_slice());
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                Service::new,
+                |mut service| apply(&runtime, &mut service, &records),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn output_writing(c: &mut Criterion) {
+    let records = (1..=1_000_000)
+        .map(|client| OutputRecord {
+            client,
+            available: client as f64 * 1.5,
+            held: 0.25,
+            total: client as f64 * 1.5 + 0.25,
+            locked: client % 100 == 0,
+        })
+        .collect::<Vec<_>>();
+    let mut group = c.benchmark_group("output_writer");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(1_000_000));
+    group.bench_function("write_1m_clients", |b| {
+        b.iter(|| OutputWriter::new().write(io::sink(), records.iter()))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    csv_parsing,
+    handle_per_type,
+    workloads,
+    output_writing
+);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+//! Raw partner file bytes through both reader modes and the engine
+#![no_main]
+
+use bank::input_file_reader::InputFileReader;
+use bank_fuzz::check_engine;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let records = InputFileReader::parse(data);
+    if let Ok(strict) = InputFileReader::parse_strict(data) {
+        // both modes keep the same records, the strict one also keeps the failures
+        let parsed = strict.iter().filter(|(_, record)| record.is_ok()).count();
+        assert_eq!(parsed, records.len());
+    }
+    check_engine(&records);
+});
@@ -0,0 +1,63 @@
+//! Structured record sequences, so that most inputs reach the engine rather than the parser
+#![no_main]
+
+use arbitrary::Arbitrary;
+use bank::input_file_reader::{InputFileRecord, InputFileRecordType};
+use bank_fuzz::check_engine;
+use chrono::DateTime;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+enum RecordType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Reversal,
+    Authorize,
+    Capture,
+    Void,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Record {
+    r#type: RecordType,
+    // narrow ranges so that records refer to each other
+    client: u8,
+    tx: u8,
+    amount: Option<f64>,
+    /// Seconds since the epoch
+    timestamp: Option<u32>,
+    reason: Option<String>,
+}
+
+impl From<Record> for InputFileRecord {
+    fn from(record: Record) -> Self {
+        Self {
+            r#type: match record.r#type {
+                RecordType::Deposit => InputFileRecordType::Deposit,
+                RecordType::Withdrawal => InputFileRecordType::Withdrawal,
+                RecordType::Dispute => InputFileRecordType::Dispute,
+                RecordType::Resolve => InputFileRecordType::Resolve,
+                RecordType::Chargeback => InputFileRecordType::Chargeback,
+                RecordType::Reversal => InputFileRecordType::Reversal,
+                RecordType::Authorize => InputFileRecordType::Authorize,
+                RecordType::Capture => InputFileRecordType::Capture,
+                RecordType::Void => InputFileRecordType::Void,
+            },
+            client: u64::from(record.client % 8),
+            tx: u64::from(record.tx % 32),
+            amount: record.amount,
+            timestamp: record
+                .timestamp
+                .and_then(|secs| DateTime::from_timestamp(i64::from(secs), 0)),
+            reason: record.reason,
+        }
+    }
+}
+
+fuzz_target!(|records: Vec<Record>| {
+    let records: Vec<InputFileRecord> = records.into_iter().map(Into::into).collect();
+    check_engine(&records);
+});
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Seeds the csv_reader corpus with the test assets input files
+set -e
+cd "$(dirname "$0")"
+mkdir -p corpus/csv_reader
+for file in ../tests/assets/transactions*.csv ../tests/assets/batch*.csv; do
+    cp "$file" corpus/csv_reader/
+done
@@ -0,0 +1,50 @@
+//! Engine harness shared by the fuzz targets
+use bank::{
+    config::Config,
+    input_file_reader::InputFileRecord,
+    invariants::InvariantCheck,
+    output_record::OutputRecordProvider,
+    service::{Service, TransactionRecordHandler},
+};
+use std::sync::OnceLock;
+use tokio::runtime::{Builder, Runtime};
+
+/// The test assets config, so that fees, limits, rules and expiries are exercised too
+fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let mut config: Config = toml::from_str(include_str!("../../tests/assets/config.toml"))
+            .expect("test assets config should parse");
+        config.invariants.check = InvariantCheck::PerTransaction;
+        // the reorder buffer would hold records back past the end of the input
+        config.reordering = None;
+        config
+    })
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        Builder::new_current_thread()
+            .build()
+            .expect("runtime should start")
+    })
+}
+
+/// Applies the records to a fresh engine, panics on any invariant violation.
+/// Rejected records are expected, the engine must only stay consistent
+pub fn check_engine(records: &[InputFileRecord]) {
+    let mut service = Service::with_config(config().clone());
+    for record in records {
+        let _ = runtime().block_on(service.handle(record));
+    }
+    service.finish_run();
+    if let Some(violation) = service.invariant_violations().next() {
+        panic!("invariant violated: {violation:?}\nrecords: {records:#?}");
+    }
+    // reports and snapshots are built from the same state
+    service.get_records().for_each(drop);
+    service.get_statement_records().for_each(drop);
+    service.get_history_records().for_each(drop);
+    service.export_snapshot();
+}
@@ -0,0 +1,36 @@
+#!/bin/sh
+# Measures the elapsed time and peak memory of a run over a generated transactions file.
+# Usage: scripts/measure_run.sh ROWS [CLIENTS], BANK=path/to/bank measures another build.
+# Peak memory is read from /proc, so this only works on Linux.
+set -e
+cd "$(dirname "$0")/.."
+rows=$1
+clients=${2:-10000}
+if [ -z "$rows" ]; then
+    echo "usage: $0 ROWS [CLIENTS]" >&2
+    exit 1
+fi
+cargo build --release --quiet --bins
+bank=${BANK:-target/release/bank}
+input=target/measure/generated-$rows-$clients.csv
+mkdir -p target/measure
+if [ ! -f "$input" ]; then
+    # few chargebacks, so that locked clients do not leave most rows ignored
+    target/release/generate --clients "$clients" --transactions "$rows" \
+        --dispute-rate 0.05 --chargeback-rate 0.01 --seed 1 --output "$input"
+fi
+
+start=$(date +%s.%N)
+"$bank" "$input" > target/measure/output.csv 2> /dev/null &
+pid=$!
+peak=0
+while kill -0 "$pid" 2> /dev/null; do
+    hwm=$(awk '/VmHWM/ { print $2 }' "/proc/$pid/status" 2> /dev/null || true)
+    if [ -n "$hwm" ]; then
+        peak=$hwm
+    fi
+    sleep 0.1
+done
+wait "$pid"
+end=$(date +%s.%N)
+echo "rows: $rows, clients: $clients, elapsed: $(awk "BEGIN { printf \"%.1f\", $end - $start }") s, peak: $((peak / 1024)) MB"
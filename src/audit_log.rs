@@ -0,0 +1,366 @@
+//! Append-only log of the state changes applied by the engine, one JSON entry per line. Every
+//! entry includes the hash of the entry before it, so altering, removing or reordering an entry
+//! breaks the chain from that entry on
+use crate::{
+    input_file_reader::{InputFileRecord, InputFileRecordType},
+    pipeline::ResultStatus,
+};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// Previous hash of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Written after the rest of the entry, the hash covers every byte before it
+const HASH_FIELD: &str = ",\"hash\":\"";
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A transaction record handled by the engine, rejected records may be stored as failed
+    Record {
+        r#type: InputFileRecordType,
+        client: u64,
+        tx: u64,
+        amount: Option<f64>,
+        timestamp: Option<DateTime<Utc>>,
+        reason: Option<String>,
+        status: ResultStatus,
+        error: Option<String>,
+    },
+    DisputeExpired {
+        tx: u64,
+        client: u64,
+    },
+    AuthorizationExpired {
+        tx: u64,
+    },
+    InterestPosted {
+        tx: u64,
+        client: u64,
+        amount: f64,
+    },
+    /// Every change since the start of the failed batch was undone
+    RolledBack {
+        records: usize,
+    },
+    /// The state went back to a checkpoint and `records` records were replayed from there in
+    /// event time order, the late record `tx` among them. The late record and the replayed
+    /// records whose outcome changed are audited in the entries that follow, in replay order
+    Reprocessed {
+        tx: u64,
+        records: usize,
+    },
+}
+
+impl AuditEvent {
+    /// `rejection` is the error message of a rejected record, see `Service::rejection`
+    pub fn record(record: &InputFileRecord, rejection: Option<&str>) -> AuditEvent {
+        AuditEvent::Record {
+            r#type: record.r#type,
+            client: record.client,
+            tx: record.tx,
+            amount: record.amount,
+            timestamp: record.timestamp,
+            reason: record.reason.clone(),
+            status: match rejection {
+                None => ResultStatus::Accepted,
+                Some(_) => ResultStatus::Rejected,
+            },
+            error: rejection.map(str::to_string),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    seq: u64,
+    prev_hash: &'a str,
+    recorded_at: DateTime<Utc>,
+    event: &'a AuditEvent,
+}
+
+/// The chain fields of an entry, the event itself is covered by the hash only
+#[derive(Deserialize)]
+struct AuditLink {
+    seq: u64,
+    prev_hash: String,
+}
+
+/// Appends entries after the last one of the log, across runs.
+///
+/// Write errors do not stop the engine, the first one is kept and returned by `finish`
+pub struct AuditLog {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    seq: u64,
+    last_hash: String,
+    error: Option<anyhow::Error>,
+}
+
+impl AuditLog {
+    /// Starts a new chain
+    pub fn new(writer: impl Write + Send + 'static) -> AuditLog {
+        Self {
+            writer: BufWriter::new(Box::new(writer)),
+            seq: 0,
+            last_hash: GENESIS_HASH.to_string(),
+            error: None,
+        }
+    }
+
+    /// Continues the chain of the file, or starts it if there is no file yet.
+    /// Only the last entry is read, `verify` checks the ones before it
+    pub fn open(path: &str) -> anyhow::Result<AuditLog> {
+        let mut log = Self::new(std::io::sink());
+        if Path::new(path).exists() {
+            let file = File::open(path).context(format!("failed to open audit log: {path}"))?;
+            // improvement: read the last line from the end of the file
+            let mut last_line = None;
+            for line in BufReader::new(file).split(b'\n') {
+                last_line = Some(line.context(format!("failed to read audit log: {path}"))?);
+            }
+            if let Some(line) = last_line {
+                let Some((_, link, hash)) = parse_entry(&line) else {
+                    bail!("cannot continue audit log {path}, its last entry is invalid");
+                };
+                log.seq = link.seq;
+                log.last_hash = hash.to_string();
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("failed to open audit log: {path}"))?;
+        log.writer = BufWriter::new(Box::new(file));
+        Ok(log)
+    }
+
+    pub fn append(&mut self, recorded_at: DateTime<Utc>, event: &AuditEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let entry = AuditEntry {
+            seq: self.seq + 1,
+            prev_hash: &self.last_hash,
+            recorded_at,
+            event,
+        };
+        let outcome = serde_json::to_string(&entry)
+            .context("failed to serialize audit entry")
+            .and_then(|entry| {
+                // the entry without its closing brace, followed by the hash of it
+                let body = &entry[..entry.len() - 1];
+                let hash = hash(body);
+                writeln!(self.writer, "{body}{HASH_FIELD}{hash}\"}}")
+                    .context("failed to write audit entry")?;
+                Ok(hash)
+            });
+        match outcome {
+            Ok(hash) => {
+                self.seq += 1;
+                self.last_hash = hash;
+            }
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// Flushes the entries, fails if any of them could not be written
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.flush().context("failed to write audit log")
+    }
+}
+
+/// Outcome of recomputing the chain of an audit log
+#[derive(Debug, Display, PartialEq)]
+pub enum Verification {
+    #[display("audit log intact, entries: {entries}, last hash: {last_hash}")]
+    Intact { entries: u64, last_hash: String },
+    #[display("audit log tampered, first tampered entry at line {line}: {reason}")]
+    Tampered { line: u64, reason: String },
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        matches!(self, Verification::Intact { .. })
+    }
+}
+
+/// Recomputes the hash chain and stops at the first entry that does not belong to it.
+///
+/// An entry altered along with the hashes of every entry after it still forms a chain, which
+/// shows in the last hash only; compare it with the one reported when the log was last verified
+// improvement: sign the entries or publish the last hash elsewhere
+pub fn verify(reader: impl BufRead) -> anyhow::Result<Verification> {
+    let mut entries = 0;
+    let mut last_hash = GENESIS_HASH.to_string();
+    for (index, line) in reader.split(b'\n').enumerate() {
+        let line = line.context("failed to read audit log")?;
+        let tampered = |reason: String| {
+            Ok(Verification::Tampered {
+                line: index as u64 + 1,
+                reason,
+            })
+        };
+        let Some((body, link, hash)) = parse_entry(&line) else {
+            return tampered("not an audit entry".to_string());
+        };
+        if self::hash(body) != hash {
+            return tampered("hash does not match the entry".to_string());
+        }
+        if link.seq != entries + 1 {
+            return tampered(format!(
+                "sequence number {}, expected {}",
+                link.seq,
+                entries + 1
+            ));
+        }
+        if link.prev_hash != last_hash {
+            // an entry altered along with its hash breaks the chain at the entry after it
+            return tampered(format!(
+                "previous hash does not match the entry at line {index}, either entry was altered"
+            ));
+        }
+        entries += 1;
+        last_hash = hash.to_string();
+    }
+    Ok(Verification::Intact { entries, last_hash })
+}
+
+/// The hashed part, the chain fields and the hash of a line, None if it is not an entry
+fn parse_entry(line: &[u8]) -> Option<(&str, AuditLink, &str)> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (body, hash) = line.strip_suffix("\"}")?.rsplit_once(HASH_FIELD)?;
+    let link = serde_json::from_str(&format!("{body}}}")).ok()?;
+    Some((body, link, hash))
+}
+
+fn hash(body: &str) -> String {
+    hex::encode(Sha256::digest(body.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        service::{Service, TransactionRecordHandler},
+        shared_buffer::SharedBuffer,
+    };
+    use std::sync::{Arc, Mutex};
+
+    fn write_log(events: usize) -> Vec<String> {
+        let buffer = SharedBuffer::default();
+        let mut log = AuditLog::new(buffer.clone());
+        let recorded_at = "2024-01-01T09:00:00Z".parse().unwrap();
+        for tx in 0..events as u64 {
+            log.append(recorded_at, &AuditEvent::AuthorizationExpired { tx });
+        }
+        log.finish().expect("log should be written");
+        buffer.contents().lines().map(str::to_string).collect()
+    }
+
+    fn verify_lines(lines: &[String]) -> Verification {
+        verify(lines.join("\n").as_bytes()).expect("log should be readable")
+    }
+
+    #[test]
+    fn entries_chain_to_the_previous_one() {
+        let lines = write_log(3);
+        assert!(lines[0].starts_with(&format!(
+            "{{\"seq\":1,\"prev_hash\":\"{GENESIS_HASH}\",\"recorded_at\":\"2024-01-01T09:00:00Z\",\
+             \"event\":{{\"kind\":\"authorization_expired\",\"tx\":0}},\"hash\":\""
+        )));
+        let (_, _, first_hash) = parse_entry(lines[0].as_bytes()).unwrap();
+        let (_, second, _) = parse_entry(lines[1].as_bytes()).unwrap();
+        assert_eq!(second.prev_hash, first_hash);
+        match verify_lines(&lines) {
+            Verification::Intact { entries, .. } => assert_eq!(entries, 3),
+            tampered => panic!("log should be intact: {tampered}"),
+        }
+    }
+
+    #[test]
+    fn pinpoints_the_first_tampered_entry() {
+        let lines = write_log(4);
+        let tampered_at = |lines: &[String]| match verify_lines(lines) {
+            Verification::Tampered { line, .. } => line,
+            intact => panic!("tampering should be detected: {intact}"),
+        };
+
+        let mut altered = lines.clone();
+        altered[1] = altered[1].replace("\"tx\":1", "\"tx\":7");
+        assert_eq!(tampered_at(&altered), 2);
+
+        let mut removed = lines.clone();
+        removed.remove(2);
+        assert_eq!(tampered_at(&removed), 3);
+
+        let mut swapped = lines.clone();
+        swapped.swap(1, 2);
+        assert_eq!(tampered_at(&swapped), 2);
+
+        let mut garbled = lines;
+        garbled[3].truncate(20);
+        assert_eq!(tampered_at(&garbled), 4);
+    }
+
+    #[test]
+    fn continues_the_chain_across_runs() {
+        let path = std::env::temp_dir().join(format!("audit_log_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let recorded_at = "2024-01-01T09:00:00Z".parse().unwrap();
+        for tx in 0..2 {
+            let mut log = AuditLog::open(path).expect("log should open");
+            log.append(recorded_at, &AuditEvent::AuthorizationExpired { tx });
+            log.finish().expect("log should be written");
+        }
+        let verification = verify(BufReader::new(File::open(path).unwrap()));
+        std::fs::remove_file(path).unwrap();
+        match verification.unwrap() {
+            Verification::Intact { entries, .. } => assert_eq!(entries, 2),
+            tampered => panic!("log should be intact: {tampered}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn records_stored_as_failed_are_rejected() {
+        let buffer = SharedBuffer::default();
+        let audit_log = Arc::new(Mutex::new(AuditLog::new(buffer.clone())));
+        let mut service = Service::new();
+        service.set_audit_log(Some(audit_log.clone()));
+        for (r#type, tx, amount) in [
+            (InputFileRecordType::Deposit, 1, 5.0),
+            (InputFileRecordType::Withdrawal, 2, 50.0),
+        ] {
+            let record = InputFileRecord {
+                r#type,
+                client: 1,
+                tx,
+                amount: Some(amount),
+                timestamp: None,
+                reason: None,
+            };
+            service
+                .handle(&record)
+                .await
+                .expect("record should be handled");
+        }
+        audit_log.lock().unwrap().finish().unwrap();
+        let contents = buffer.contents();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert!(lines[0].contains("\"status\":\"accepted\",\"error\":null"));
+        assert!(lines[1]
+            .contains("\"status\":\"rejected\",\"error\":\"transaction stored as failed: 2\""));
+    }
+}
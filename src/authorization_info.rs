@@ -0,0 +1,10 @@
+use crate::client_id::ClientId;
+use chrono::{DateTime, Utc};
+
+/// Funds reserved by an open authorization, part of the client's held funds
+#[derive(Debug, Copy, Clone)]
+pub struct AuthorizationInfo {
+    pub client: ClientId,
+    pub amount: f64,
+    pub authorized_at: DateTime<Utc>,
+}
@@ -0,0 +1,16 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthorizationConfig {
+    /// Authorizations neither captured nor voided within this are released automatically
+    pub expire_after_hours: Option<u32>,
+}
+
+impl AuthorizationConfig {
+    pub fn is_expired(&self, authorized_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.expire_after_hours
+            .is_some_and(|hours| now - authorized_at > Duration::hours(hours as i64))
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{
+    audit_log::AuditEvent, input_file_reader::InputFileRecord, metrics::Metrics,
+    output_record::BatchErrorRecord, service::Service,
+};
+use std::sync::Arc;
+use tracing::error;
+
+/// Applies every record of a file or none of them. Processing goes on after a failure so the
+/// report lists every failing record, although later failures may follow from earlier ones.
+/// On failure the engine goes back to a checkpoint taken before the file, velocity windows,
+/// run summary, flagged records and rule state included. The records are counted in the
+/// metrics once the file is applied, or only as rolled back
+pub async fn apply_batch(
+    service: &mut Service,
+    records: Vec<(u64, anyhow::Result<InputFileRecord>)>,
+) -> Vec<BatchErrorRecord> {
+    // improvement: snapshot only the entries the file touches instead of the whole state
+    let checkpoint = service.checkpoint();
+    let metrics = service.metrics().cloned();
+    if metrics.is_some() {
+        service.set_metrics(Some(Arc::new(Metrics::default())));
+    }
+    let mut errors = Vec::new();
+    let records_count = records.len();
+    for (line, record) in records {
+        let outcome = match &record {
+            Ok(record) => service.handle_strict(record).await,
+            Err(err) => Err(anyhow::anyhow!("invalid record: {err}")),
+        };
+        if let Err(err) = outcome {
+            match &record {
+                Ok(record) => record
+                    .span()
+                    .in_scope(|| error!("batch failure at line {line}: {err}")),
+                Err(_) => error!("batch failure at line {line}: {err}"),
+            }
+            errors.push(BatchErrorRecord {
+                line,
+                tx: record.as_ref().ok().map(|record| record.tx),
+                error: err.to_string(),
+            });
+        }
+    }
+    let batch_metrics = service.metrics().cloned();
+    service.set_metrics(metrics.clone());
+    if !errors.is_empty() {
+        // the audit log keeps the records applied before the rollback, followed by the rollback
+        service.audit(|| AuditEvent::RolledBack {
+            records: records_count,
+        });
+        service.restore(checkpoint);
+        if let Some(metrics) = &metrics {
+            metrics.roll_back(records_count);
+        }
+    } else if let (Some(metrics), Some(batch_metrics)) = (&metrics, &batch_metrics) {
+        metrics.merge(batch_metrics);
+    }
+    service.refresh_metrics();
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit_log::{self, AuditLog},
+        config::Config,
+        input_file_reader::{InputFileReader, InputFileRecordType},
+        output_record::OutputRecordProvider,
+    };
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn failing_batch_is_rolled_back() {
+        let mut service = Service::new();
+        let records = InputFileReader::new("tests/assets/transactions.csv".to_string())
+            .read_file_strict()
+            .expect("file should be readable");
+        let errors = apply_batch(&mut service, records).await;
+        assert!(errors.is_empty());
+        assert_eq!(service.get_records().count(), 2);
+
+        let mut service = Service::new();
+        let records = InputFileReader::new("tests/assets/batch_invalid.csv".to_string())
+            .read_file_strict()
+            .expect("file should be readable");
+        let errors = apply_batch(&mut service, records).await;
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| (error.line, error.tx))
+                .collect::<Vec<_>>(),
+            vec![(3, None), (4, Some(3))]
+        );
+        assert_eq!(service.get_records().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_run_level_state() {
+        let mut service = Service::new();
+        service.set_metrics(Some(Arc::default()));
+        let records = InputFileReader::new("tests/assets/transactions.csv".to_string())
+            .read_file_strict()
+            .expect("file should be readable");
+        assert!(apply_batch(&mut service, records).await.is_empty());
+        let balances = |service: &Service| {
+            service
+                .get_records()
+                .map(|record| (record.client, record.available, record.held))
+                .collect::<Vec<_>>()
+        };
+        let before = balances(&service);
+
+        let record = |r#type, tx, amount| InputFileRecord {
+            r#type,
+            client: 2,
+            tx,
+            amount: Some(amount),
+            timestamp: None,
+            reason: None,
+        };
+        let records = vec![
+            (2, Ok(record(InputFileRecordType::Deposit, 10, 5.0))),
+            (3, Ok(record(InputFileRecordType::Withdrawal, 11, 100.0))),
+        ];
+        assert_eq!(apply_batch(&mut service, records).await.len(), 1);
+        assert_eq!(balances(&service), before);
+        assert_eq!(service.run_summary().records, 9);
+        let output = service.metrics().unwrap().render();
+        for line in [
+            "bank_records_total{type=\"deposit\",outcome=\"accepted\"} 4",
+            "bank_records_total{type=\"withdrawal\",outcome=\"rejected\"} 0",
+            "bank_rolled_back_records_total 2",
+        ] {
+            assert!(
+                output.lines().any(|l| l == line),
+                "{line} missing in:\n{output}"
+            );
+        }
+        // the rolled back deposit left no transaction behind
+        service
+            .handle_strict(&record(InputFileRecordType::Deposit, 10, 1.0))
+            .await
+            .expect("deposit should be applied");
+    }
+
+    #[tokio::test]
+    async fn rolled_back_withdrawals_do_not_count_against_limits() {
+        let config: Config = toml::from_str(
+            r#"
+            [limits]
+            max_withdrawals = 1
+            "#,
+        )
+        .expect("config should parse");
+        let mut service = Service::with_config(config);
+        let record = |r#type, tx, amount| InputFileRecord {
+            r#type,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            timestamp: Some("2024-01-01T00:00:00Z".parse().unwrap()),
+            reason: None,
+        };
+        let records = vec![
+            (2, Ok(record(InputFileRecordType::Deposit, 1, 10.0))),
+            (3, Ok(record(InputFileRecordType::Withdrawal, 2, 5.0))),
+            (4, Ok(record(InputFileRecordType::Withdrawal, 3, 100.0))),
+        ];
+        assert_eq!(apply_batch(&mut service, records).await.len(), 1);
+
+        let records = vec![
+            (2, Ok(record(InputFileRecordType::Deposit, 4, 10.0))),
+            (3, Ok(record(InputFileRecordType::Withdrawal, 5, 5.0))),
+        ];
+        assert!(apply_batch(&mut service, records).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rollback_is_audited() {
+        let path = std::env::temp_dir().join(format!("batch_audit_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let audit_log = Arc::new(Mutex::new(AuditLog::open(path).unwrap()));
+        let mut service = Service::new();
+        service.set_audit_log(Some(audit_log.clone()));
+        let records = InputFileReader::new("tests/assets/batch_invalid.csv".to_string())
+            .read_file_strict()
+            .expect("file should be readable");
+        let records_count = records.len();
+        apply_batch(&mut service, records).await;
+        audit_log.lock().unwrap().finish().unwrap();
+        let log = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // records that failed to parse never reach the engine
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.contains("\"event\":{\"kind\":\"record\""))
+                .count(),
+            records_count - 1
+        );
+        assert!(lines.last().unwrap().contains(&format!(
+            "\"event\":{{\"kind\":\"rolled_back\",\"records\":{records_count}}}"
+        )));
+        assert!(audit_log::verify(log.as_bytes()).unwrap().is_intact());
+    }
+}
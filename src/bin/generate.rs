@@ -0,0 +1,32 @@
+use bank::{
+    generator::{write_rows, Format, Generator, GeneratorConfig},
+    logging,
+};
+use clap::Parser;
+use std::{fs::File, io, process};
+use tracing::error;
+
+/// Generates synthetic transaction files for load tests and demos
+#[derive(Debug, Parser)]
+struct Cli {
+    #[command(flatten)]
+    config: GeneratorConfig,
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+    /// Output file, stdout if not set
+    #[arg(long)]
+    output: Option<String>,
+}
+
+fn main() {
+    logging::init();
+    let cli = Cli::parse();
+    let result = Generator::new(cli.config).and_then(|generator| match &cli.output {
+        Some(path) => write_rows(File::create(path)?, cli.format, generator),
+        None => write_rows(io::stdout().lock(), cli.format, generator),
+    });
+    if let Err(err) = result {
+        error!("{err:#}");
+        process::exit(1);
+    }
+}
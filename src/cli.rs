@@ -0,0 +1,140 @@
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Simple command-line tool for handling basic transactions",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub run: Option<RunArgs>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Process transactions, then accrue and post daily interest up to the given date
+    Accrue {
+        #[arg(long)]
+        date: NaiveDate,
+        /// Write the interest calculation basis to the given file
+        #[arg(long)]
+        interest_report: Option<String>,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Check what processing the transactions file would do without saving any state.
+    /// Exits with code 3 when any record would be rejected
+    Validate {
+        /// Input file with transactions
+        transactions_file: String,
+        #[command(flatten)]
+        engine: EngineArgs,
+        /// Write the records that would be rejected to the given file
+        #[arg(long)]
+        error_report: Option<String>,
+    },
+    /// Recompute the hash chain of an audit log and report the first tampered entry.
+    /// Exits with code 3 when the chain is broken
+    VerifyAudit {
+        /// Audit log written by runs with --audit-log
+        audit_log: String,
+    },
+    /// Process transactions, then materialize scheduled instructions due up to the given date
+    RunSchedule {
+        #[arg(long)]
+        until: NaiveDate,
+        /// File with standing orders and future-dated transactions
+        #[arg(long)]
+        schedule: String,
+        /// Write every attempt made for a scheduled instruction to the given file
+        #[arg(long)]
+        schedule_report: Option<String>,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+}
+
+/// Configuration and starting state of the engine
+#[derive(Debug, Args)]
+pub struct EngineArgs {
+    /// Engine configuration file
+    #[arg(long)]
+    pub config: Option<String>,
+    /// Client configuration file with credit limits
+    #[arg(long)]
+    pub client_config: Option<String>,
+    /// Start from the engine state saved in the given snapshot
+    #[arg(long)]
+    pub load_snapshot: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Input file with transactions
+    // clap leaves the group of a struct with flattened fields empty, and an optional flattened
+    // struct is only parsed when its group is present, so the file is added to it explicitly
+    #[arg(group = "RunArgs")]
+    pub transactions_file: String,
+    #[command(flatten)]
+    pub engine: EngineArgs,
+    /// Save the engine state to the given snapshot at the end of the run
+    #[arg(long)]
+    pub save_snapshot: Option<String>,
+    /// Write client statements with overdraft usage to the given file
+    #[arg(long)]
+    pub statement: Option<String>,
+    /// Write fee postings to the given file
+    #[arg(long)]
+    pub fee_report: Option<String>,
+    /// Write transactions hit by fraud rules to the given file
+    #[arg(long)]
+    pub flagged_report: Option<String>,
+    /// Write the transaction history, including reversal reasons, to the given file
+    #[arg(long)]
+    pub history_report: Option<String>,
+    /// Write accounting invariant violations found by the configured checks to the given file
+    #[arg(long)]
+    pub invariant_report: Option<String>,
+    /// Write the run summary to the given file
+    #[arg(long)]
+    pub summary: Option<String>,
+    /// Write records that arrived beyond the lateness bound to the given file
+    #[arg(long)]
+    pub late_report: Option<String>,
+    /// Stream the result of every transaction record to the given file as it is applied
+    #[arg(long, conflicts_with = "batch")]
+    pub results: Option<String>,
+    /// Write Prometheus metrics to the given file at the end of the run
+    #[arg(long)]
+    pub metrics: Option<String>,
+    /// Serve Prometheus metrics over HTTP at the given address while the run is in progress
+    #[arg(long)]
+    pub metrics_addr: Option<String>,
+    /// Append every state change to the given hash-chained audit log, continuing its chain
+    #[arg(long)]
+    pub audit_log: Option<String>,
+    /// Apply the transactions file all-or-nothing: any failing record rolls back the whole file
+    #[arg(long)]
+    pub batch: bool,
+    /// Write the records that made the batch fail to the given file
+    #[arg(long, requires = "batch")]
+    pub error_report: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_level_run_args() {
+        let cli = Cli::try_parse_from(["bank", "--config", "config.toml", "transactions.csv"])
+            .expect("arguments should parse");
+        assert!(cli.command.is_none());
+        let run = cli.run.expect("run arguments should be set");
+        assert_eq!(run.transactions_file, "transactions.csv");
+        assert_eq!(run.engine.config.as_deref(), Some("config.toml"));
+    }
+}
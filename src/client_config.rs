@@ -0,0 +1,84 @@
+use crate::{client_id::ClientId, limits::WithdrawalLimits};
+use anyhow::{bail, Context};
+use csv::{ReaderBuilder, Trim};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ClientConfig {
+    pub credit_limit: f64,
+    /// Overrides of the global withdrawal limits
+    pub limits: WithdrawalLimits,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientConfigRecord {
+    client: u16,
+    #[serde(default)]
+    credit_limit: f64,
+    #[serde(default)]
+    max_withdrawals: Option<u32>,
+    #[serde(default)]
+    max_withdrawal_total: Option<f64>,
+    #[serde(default)]
+    max_single_withdrawal: Option<f64>,
+}
+
+pub struct ClientConfigReader {
+    path: String,
+}
+
+impl ClientConfigReader {
+    pub fn new(path: String) -> ClientConfigReader {
+        Self { path }
+    }
+
+    /// Unlike transaction files, every row has to be valid
+    pub fn read_file(&self) -> anyhow::Result<HashMap<ClientId, ClientConfig>> {
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_path(&self.path)
+            .context(format!("failed to open client config file: {}", self.path))?;
+        let mut clients = HashMap::new();
+        for record in rdr.deserialize() {
+            let ClientConfigRecord {
+                client,
+                credit_limit,
+                max_withdrawals,
+                max_withdrawal_total,
+                max_single_withdrawal,
+            } = record.context(format!("invalid client config file: {}", self.path))?;
+            if credit_limit.is_nan() || credit_limit < 0f64 {
+                bail!("invalid credit limit for client {client}: {credit_limit}");
+            }
+            let config = ClientConfig {
+                credit_limit,
+                limits: WithdrawalLimits {
+                    max_withdrawals,
+                    max_withdrawal_total,
+                    max_single_withdrawal,
+                },
+            };
+            if clients.insert(ClientId::new(client), config).is_some() {
+                bail!("duplicate client in client config: {client}");
+            }
+        }
+        Ok(clients)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_credit_limits() {
+        let clients = ClientConfigReader::new("tests/assets/clients.csv".to_string())
+            .read_file()
+            .expect("client config should be valid");
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[&ClientId::new(2)].credit_limit, 100.0);
+        assert_eq!(clients[&ClientId::new(2)].limits.max_withdrawals, Some(5));
+        assert_eq!(clients[&ClientId::new(1)].limits.max_withdrawals, None);
+    }
+}
@@ -1,6 +1,12 @@
+use crate::invariants::tolerance;
+use anyhow::bail;
+
 #[derive(Debug)]
 pub struct ClientInfo {
+    /// Held funds, both disputed and authorized
     pub on_hold: f64,
+    /// Part of `on_hold` reserved by open authorizations
+    pub authorized: f64,
     pub available: f64,
     pub is_locked: bool,
 }
@@ -9,8 +15,30 @@ impl Default for ClientInfo {
     fn default() -> Self {
         Self {
             on_hold: 0f64,
+            authorized: 0f64,
             available: 0f64,
             is_locked: false,
         }
     }
 }
+
+impl ClientInfo {
+    /// Total funds as reported, available and held
+    pub fn total(&self) -> f64 {
+        self.available + self.on_hold
+    }
+
+    /// Takes `amount` out of the held funds. A shortfall within the rounding tolerance
+    /// leaves nothing on hold, anything larger is an error and changes nothing
+    pub fn release_held(&mut self, amount: f64) -> anyhow::Result<()> {
+        let on_hold = self.on_hold - amount;
+        if on_hold < -tolerance(amount) {
+            bail!(
+                "held funds below released amount, held: {}, released: {amount}",
+                self.on_hold
+            );
+        }
+        self.on_hold = on_hold.max(0f64);
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{client_id::ClientId, client_info::ClientInfo};
+use std::ops::Index;
+
+/// Client accounts indexed directly by client id, iterated in client id order.
+/// Client ids are `u16`, so the table never grows past 65536 slots
+#[derive(Debug, Default)]
+pub struct ClientTable {
+    slots: Vec<Option<ClientInfo>>,
+    len: usize,
+}
+
+impl ClientTable {
+    fn slot_index(client_id: &ClientId) -> usize {
+        usize::from(client_id.value())
+    }
+
+    pub fn get(&self, client_id: &ClientId) -> Option<&ClientInfo> {
+        self.slots.get(Self::slot_index(client_id))?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, client_id: &ClientId) -> Option<&mut ClientInfo> {
+        self.slots.get_mut(Self::slot_index(client_id))?.as_mut()
+    }
+
+    pub fn contains_key(&self, client_id: &ClientId) -> bool {
+        self.get(client_id).is_some()
+    }
+
+    /// The account of the client, opened with no funds if the client is new
+    pub fn get_or_default(&mut self, client_id: ClientId) -> &mut ClientInfo {
+        let index = Self::slot_index(&client_id);
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, || None);
+        }
+        let slot = &mut self.slots[index];
+        if slot.is_none() {
+            self.len += 1;
+        }
+        slot.get_or_insert_with(ClientInfo::default)
+    }
+
+    pub fn insert(&mut self, client_id: ClientId, info: ClientInfo) {
+        *self.get_or_default(client_id) = info;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &ClientInfo)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let info = slot.as_ref()?;
+            Some((ClientId::new(index as u16), info))
+        })
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.iter().map(|(client_id, _)| client_id)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &ClientInfo> {
+        self.iter().map(|(_, info)| info)
+    }
+}
+
+impl Index<&ClientId> for ClientTable {
+    type Output = ClientInfo;
+
+    fn index(&self, client_id: &ClientId) -> &ClientInfo {
+        self.get(client_id).expect("client id should exist")
+    }
+}
+
+impl FromIterator<(ClientId, ClientInfo)> for ClientTable {
+    fn from_iter<I: IntoIterator<Item = (ClientId, ClientInfo)>>(iter: I) -> Self {
+        let mut table = ClientTable::default();
+        for (client_id, info) in iter {
+            table.insert(client_id, info);
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iterates_in_client_id_order() {
+        let mut table = ClientTable::default();
+        table.get_or_default(ClientId::new(u16::MAX)).available = 1.0;
+        table.get_or_default(ClientId::new(3)).available = 2.0;
+        table.get_or_default(ClientId::new(3)).on_hold = 1.0;
+        assert_eq!(table.len(), 2);
+        assert!(!table.contains_key(&ClientId::new(4)));
+        assert_eq!(table[&ClientId::new(3)].on_hold, 1.0);
+        assert_eq!(
+            table.keys().map(|id| id.value()).collect::<Vec<_>>(),
+            vec![3, u16::MAX]
+        );
+    }
+}
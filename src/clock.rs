@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+
+/// Source of the current time for records without a timestamp
+pub trait Clock: Send {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[cfg(test)]
+pub mod test_clock {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Clock that only moves when told to, clones share the same time
+    #[derive(Clone)]
+    pub struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+    impl ManualClock {
+        pub fn new(now: DateTime<Utc>) -> ManualClock {
+            Self(Arc::new(Mutex::new(now)))
+        }
+
+        pub fn set(&self, now: DateTime<Utc>) {
+            *self.0.lock().unwrap() = now;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+}
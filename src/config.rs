@@ -0,0 +1,36 @@
+use crate::{
+    authorization_policy::AuthorizationConfig, client_config::ClientConfig, client_id::ClientId,
+    dispute_policy::DisputeConfig, fee_schedule::FeeSchedule, fraud_rules::RuleConfig,
+    interest::InterestConfig, invariants::InvariantConfig, limits::LimitsConfig,
+    pipeline::PipelineConfig, reorder_buffer::ReorderConfig, scheduler::ScheduleConfig,
+};
+use anyhow::Context;
+use serde::Deserialize;
+use std::{collections::HashMap, fs};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub fees: FeeSchedule,
+    pub interest: InterestConfig,
+    pub limits: LimitsConfig,
+    pub rules: Vec<RuleConfig>,
+    pub disputes: DisputeConfig,
+    pub authorizations: AuthorizationConfig,
+    pub schedule: ScheduleConfig,
+    pub invariants: InvariantConfig,
+    /// Records are applied in file order unless set
+    pub reordering: Option<ReorderConfig>,
+    pub pipeline: PipelineConfig,
+    /// Loaded separately from the client config file
+    #[serde(skip)]
+    pub clients: HashMap<ClientId, ClientConfig>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> anyhow::Result<Config> {
+        let content =
+            fs::read_to_string(path).context(format!("failed to open config file: {path}"))?;
+        toml::from_str(&content).context(format!("failed to parse config file: {path}"))
+    }
+}
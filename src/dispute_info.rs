@@ -0,0 +1,8 @@
+use crate::client_id::ClientId;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Copy, Clone)]
+pub struct DisputeInfo {
+    pub client: ClientId,
+    pub opened_at: DateTime<Utc>,
+}
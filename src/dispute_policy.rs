@@ -0,0 +1,23 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputeConfig {
+    /// Transactions older than this can no longer be disputed
+    pub window_days: Option<u32>,
+    /// Disputes open for longer than this are resolved automatically
+    pub expire_after_days: Option<u32>,
+}
+
+impl DisputeConfig {
+    pub fn is_within_window(&self, transaction_time: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.window_days
+            .is_none_or(|days| now - transaction_time <= Duration::days(days as i64))
+    }
+
+    pub fn is_expired(&self, opened_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.expire_after_days
+            .is_some_and(|days| now - opened_at > Duration::days(days as i64))
+    }
+}
@@ -0,0 +1,64 @@
+use crate::transaction_id::TransactionId;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeSet;
+
+/// Transactions waiting to expire, ordered by the time their expiry period started.
+/// All entries share one expiry period, so this is also the order they expire in.
+/// Entries settled before they expire are not removed, callers skip them when they come due
+#[derive(Debug, Default)]
+pub struct ExpiryQueue {
+    entries: BTreeSet<(DateTime<Utc>, TransactionId)>,
+}
+
+impl ExpiryQueue {
+    pub fn push(&mut self, started_at: DateTime<Utc>, transaction_id: TransactionId) {
+        self.entries.insert((started_at, transaction_id));
+    }
+
+    /// Removes the entries for which `is_expired` holds, oldest first
+    pub fn pop_expired(
+        &mut self,
+        is_expired: impl Fn(DateTime<Utc>) -> bool,
+    ) -> Vec<(DateTime<Utc>, TransactionId)> {
+        let mut expired = Vec::new();
+        while let Some(&(started_at, transaction_id)) = self.entries.first() {
+            if !is_expired(started_at) {
+                break;
+            }
+            self.entries.pop_first();
+            expired.push((started_at, transaction_id));
+        }
+        expired
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_only_expired_entries() {
+        let time = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+        let mut queue = ExpiryQueue::default();
+        queue.push(time("2024-01-03T00:00:00Z"), TransactionId::new(1));
+        queue.push(time("2024-01-01T00:00:00Z"), TransactionId::new(2));
+        queue.push(time("2024-01-02T00:00:00Z"), TransactionId::new(3));
+        let expired = queue.pop_expired(|started_at| started_at < time("2024-01-03T00:00:00Z"));
+        assert_eq!(
+            expired
+                .iter()
+                .map(|(_, transaction_id)| transaction_id.value())
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(queue.len(), 1);
+    }
+}
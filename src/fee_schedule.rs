@@ -0,0 +1,106 @@
+use crate::transaction_info::TransactionType;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "policy", rename_all = "lowercase")]
+pub enum FeePolicy {
+    Flat { amount: f64 },
+    Percentage { rate: f64 },
+    Tiered { tiers: Vec<FeeTier> },
+}
+
+/// Fee for amounts up to `up_to` (inclusive), the last tier may leave it unbounded
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeTier {
+    pub up_to: Option<f64>,
+    #[serde(default)]
+    pub flat: f64,
+    #[serde(default)]
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeeRule {
+    #[serde(flatten)]
+    pub policy: FeePolicy,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl FeeRule {
+    pub fn calculate(&self, amount: f64) -> f64 {
+        let fee = match &self.policy {
+            FeePolicy::Flat { amount } => *amount,
+            FeePolicy::Percentage { rate } => amount * rate,
+            FeePolicy::Tiered { tiers } => tiers
+                .iter()
+                .find(|tier| tier.up_to.is_none_or(|up_to| amount <= up_to))
+                .map_or(0f64, |tier| tier.flat + amount * tier.rate),
+        };
+        let fee = self.min.map_or(fee, |min| fee.max(min));
+        self.max.map_or(fee, |max| fee.min(max))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct FeeSchedule {
+    rules: HashMap<TransactionType, FeeRule>,
+}
+
+impl FeeSchedule {
+    pub fn fee_for(&self, r#type: TransactionType, amount: f64) -> f64 {
+        self.rules
+            .get(&r#type)
+            .map_or(0f64, |rule| rule.calculate(amount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(config: &str) -> FeeSchedule {
+        toml::from_str(config).expect("fee schedule should parse")
+    }
+
+    #[test]
+    fn flat_and_percentage() {
+        let fees = schedule(
+            r#"
+            [withdrawal]
+            policy = "flat"
+            amount = 0.5
+
+            [chargeback]
+            policy = "percentage"
+            rate = 0.1
+            "#,
+        );
+        assert_eq!(fees.fee_for(TransactionType::Withdrawal, 100.0), 0.5);
+        assert_eq!(fees.fee_for(TransactionType::Chargeback, 20.0), 2.0);
+        assert_eq!(fees.fee_for(TransactionType::Deposit, 20.0), 0.0);
+    }
+
+    #[test]
+    fn tiered_with_caps() {
+        let fees = schedule(
+            r#"
+            [withdrawal]
+            policy = "tiered"
+            min = 1.0
+            max = 25.0
+            tiers = [
+                { up_to = 100.0, flat = 0.5 },
+                { up_to = 1000.0, rate = 0.01 },
+                { rate = 0.05 },
+            ]
+            "#,
+        );
+        assert_eq!(fees.fee_for(TransactionType::Withdrawal, 50.0), 1.0);
+        assert_eq!(fees.fee_for(TransactionType::Withdrawal, 500.0), 5.0);
+        assert_eq!(fees.fee_for(TransactionType::Withdrawal, 10_000.0), 25.0);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{
+    client_id::ClientId,
+    client_info::ClientInfo,
+    input_file_reader::{InputFileRecord, InputFileRecordType},
+};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Display, Deserialize, Serialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Flag,
+    Reject,
+    Lock,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RuleDecision {
+    Allow,
+    Act(RuleAction, String),
+}
+
+/// Fraud rule evaluated around every handled transaction record.
+/// `client` is `None` when the record refers to a client without an account yet.
+/// A rule keeps the state it needs between records itself, checkpoints of the engine state
+/// clone the whole rule
+pub trait Rule: Send + RuleClone {
+    fn name(&self) -> &str;
+
+    fn before(&mut self, _record: &InputFileRecord, _client: Option<&ClientInfo>) -> RuleDecision {
+        RuleDecision::Allow
+    }
+
+    /// Called with the outcome of the record, it has already been applied at this point
+    /// so rejecting is reported like a flag
+    fn after(
+        &mut self,
+        _record: &InputFileRecord,
+        _client: Option<&ClientInfo>,
+        _outcome: &anyhow::Result<()>,
+    ) -> RuleDecision {
+        RuleDecision::Allow
+    }
+}
+
+/// Clones boxed rules, implemented for every rule that is `Clone`
+pub trait RuleClone {
+    fn clone_box(&self) -> Box<dyn Rule>;
+}
+
+impl<T: Rule + Clone + 'static> RuleClone for T {
+    fn clone_box(&self) -> Box<dyn Rule> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Rule> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Flags records of the given types with an amount above the threshold
+#[derive(Clone)]
+pub struct LargeAmountRule {
+    pub types: Vec<InputFileRecordType>,
+    pub threshold: f64,
+    pub action: RuleAction,
+}
+
+impl Rule for LargeAmountRule {
+    fn name(&self) -> &str {
+        "large_amount"
+    }
+
+    fn before(&mut self, record: &InputFileRecord, _client: Option<&ClientInfo>) -> RuleDecision {
+        match record.amount {
+            Some(amount) if self.types.contains(&record.r#type) && amount > self.threshold => {
+                RuleDecision::Act(
+                    self.action,
+                    format!("{} amount {amount} above {}", record.r#type, self.threshold),
+                )
+            }
+            _ => RuleDecision::Allow,
+        }
+    }
+}
+
+/// Acts on a client once the number of its failed disputes reaches the limit
+#[derive(Clone)]
+pub struct FailedDisputesRule {
+    pub max_failures: u32,
+    pub action: RuleAction,
+    failures: HashMap<ClientId, u32>,
+}
+
+impl FailedDisputesRule {
+    pub fn new(max_failures: u32, action: RuleAction) -> FailedDisputesRule {
+        Self {
+            max_failures,
+            action,
+            failures: Default::default(),
+        }
+    }
+}
+
+impl Rule for FailedDisputesRule {
+    fn name(&self) -> &str {
+        "failed_disputes"
+    }
+
+    fn after(
+        &mut self,
+        record: &InputFileRecord,
+        _client: Option<&ClientInfo>,
+        outcome: &anyhow::Result<()>,
+    ) -> RuleDecision {
+        if record.r#type != InputFileRecordType::Dispute || outcome.is_ok() {
+            return RuleDecision::Allow;
+        }
+        let failures = self
+            .failures
+            .entry(ClientId::new(record.client as u16))
+            .or_default();
+        *failures += 1;
+        if *failures < self.max_failures {
+            return RuleDecision::Allow;
+        }
+        RuleDecision::Act(self.action, format!("{failures} failed disputes"))
+    }
+}
+
+/// Rejects every record of the listed clients
+#[derive(Clone)]
+pub struct DenyClientsRule {
+    pub clients: Vec<u64>,
+}
+
+impl Rule for DenyClientsRule {
+    fn name(&self) -> &str {
+        "deny_clients"
+    }
+
+    fn before(&mut self, record: &InputFileRecord, _client: Option<&ClientInfo>) -> RuleDecision {
+        if self.clients.contains(&record.client) {
+            return RuleDecision::Act(RuleAction::Reject, "client is denied".to_string());
+        }
+        RuleDecision::Allow
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum RuleConfig {
+    LargeAmount {
+        types: Vec<InputFileRecordType>,
+        threshold: f64,
+        #[serde(default = "default_action")]
+        action: RuleAction,
+    },
+    FailedDisputes {
+        max_failures: u32,
+        #[serde(default = "default_action")]
+        action: RuleAction,
+    },
+    DenyClients {
+        clients: Vec<u64>,
+    },
+}
+
+fn default_action() -> RuleAction {
+    RuleAction::Flag
+}
+
+impl RuleConfig {
+    pub fn build(&self) -> Box<dyn Rule> {
+        match self.clone() {
+            RuleConfig::LargeAmount {
+                types,
+                threshold,
+                action,
+            } => Box::new(LargeAmountRule {
+                types,
+                threshold,
+                action,
+            }),
+            RuleConfig::FailedDisputes {
+                max_failures,
+                action,
+            } => Box::new(FailedDisputesRule::new(max_failures, action)),
+            RuleConfig::DenyClients { clients } => Box::new(DenyClientsRule { clients }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleHit {
+    pub rule: String,
+    pub action: RuleAction,
+    pub reason: String,
+}
+
+#[derive(Debug, Display)]
+#[display("rejected by rule {rule}: {reason}")]
+pub struct RuleRejection {
+    pub rule: String,
+    pub reason: String,
+}
+
+impl std::error::Error for RuleRejection {}
+
+#[derive(Clone)]
+pub struct RuleEngine {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl RuleEngine {
+    pub fn new(configs: &[RuleConfig]) -> RuleEngine {
+        Self {
+            rules: configs.iter().map(RuleConfig::build).collect(),
+        }
+    }
+
+    pub fn before(
+        &mut self,
+        record: &InputFileRecord,
+        client: Option<&ClientInfo>,
+    ) -> Vec<RuleHit> {
+        Self::collect(&mut self.rules, |rule| rule.before(record, client))
+    }
+
+    pub fn after(
+        &mut self,
+        record: &InputFileRecord,
+        client: Option<&ClientInfo>,
+        outcome: &anyhow::Result<()>,
+    ) -> Vec<RuleHit> {
+        Self::collect(&mut self.rules, |rule| {
+            match rule.after(record, client, outcome) {
+                RuleDecision::Act(RuleAction::Reject, reason) => {
+                    RuleDecision::Act(RuleAction::Flag, reason)
+                }
+                decision => decision,
+            }
+        })
+    }
+
+    fn collect(
+        rules: &mut [Box<dyn Rule>],
+        mut evaluate: impl FnMut(&mut Box<dyn Rule>) -> RuleDecision,
+    ) -> Vec<RuleHit> {
+        rules
+            .iter_mut()
+            .filter_map(|rule| match evaluate(rule) {
+                RuleDecision::Allow => None,
+                RuleDecision::Act(action, reason) => Some(RuleHit {
+                    rule: rule.name().to_string(),
+                    action,
+                    reason,
+                }),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,387 @@
+//! Synthetic transaction files for load tests and demos
+use crate::input_file_reader::InputFileRecordType;
+use anyhow::bail;
+use clap::{Args, ValueEnum};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::{BufWriter, Write},
+};
+
+/// Records between a deposit and its dispute, and between a dispute and its resolve or chargeback
+const MAX_DISPUTE_DELAY: u64 = 100;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Csv,
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct GeneratorConfig {
+    /// Number of clients
+    #[arg(long, default_value_t = 100)]
+    pub clients: u16,
+    /// Number of rows, malformed ones included
+    #[arg(long, default_value_t = 10_000)]
+    pub transactions: u64,
+    /// Share of deposits and withdrawals that are withdrawals
+    #[arg(long, default_value_t = 0.3)]
+    pub withdrawal_ratio: f64,
+    /// Share of deposits that are disputed later
+    #[arg(long, default_value_t = 0.02)]
+    pub dispute_rate: f64,
+    /// Share of disputes that end in a chargeback, the others are resolved
+    #[arg(long, default_value_t = 0.2)]
+    pub chargeback_rate: f64,
+    /// Share of rows that fail to parse
+    #[arg(long, default_value_t = 0.0)]
+    pub malformed_rate: f64,
+    /// The same seed and options generate the same file
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeneratedRecord {
+    pub r#type: InputFileRecordType,
+    pub client: u16,
+    pub tx: u64,
+    pub amount: Option<f64>,
+}
+
+/// Row that the engine's reader rejects
+#[derive(Debug, Serialize)]
+pub struct MalformedRecord {
+    pub r#type: &'static str,
+    pub client: String,
+    pub tx: String,
+    pub amount: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum GeneratedRow {
+    Record(GeneratedRecord),
+    Malformed(MalformedRecord),
+}
+
+#[derive(Debug, Default, Clone)]
+struct ClientState {
+    available: f64,
+    locked: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    Dispute {
+        client: u16,
+        tx: u64,
+        cents: u64,
+    },
+    Resolve {
+        client: u16,
+        tx: u64,
+        cents: u64,
+        chargeback: bool,
+    },
+    Chargeback {
+        client: u16,
+        tx: u64,
+        cents: u64,
+    },
+}
+
+/// Generates rows that are consistent for an engine without fees or limits: withdrawals stay
+/// within the available funds, disputes reference earlier deposits of the same client the client
+/// can still cover, disputes are resolved and resolved transactions may be charged back
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: StdRng,
+    clients: Vec<ClientState>,
+    unlocked: Vec<u16>,
+    /// Events by the row they are due at, then by creation order
+    pending: BinaryHeap<Reverse<(u64, u64, Event)>>,
+    events_count: u64,
+    rows_count: u64,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> anyhow::Result<Generator> {
+        if config.clients == 0 {
+            bail!("at least one client is required");
+        }
+        for (name, rate) in [
+            ("withdrawal ratio", config.withdrawal_ratio),
+            ("dispute rate", config.dispute_rate),
+            ("chargeback rate", config.chargeback_rate),
+            ("malformed rate", config.malformed_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("{name} must be between 0 and 1: {rate}");
+            }
+        }
+        Ok(Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            clients: vec![ClientState::default(); usize::from(config.clients)],
+            unlocked: (1..=config.clients).collect(),
+            pending: BinaryHeap::new(),
+            events_count: 0,
+            rows_count: 0,
+            config,
+        })
+    }
+
+    /// Transaction ids follow row numbers, so rows referencing an earlier one leave gaps
+    fn next_tx(&self) -> u64 {
+        self.rows_count + 1
+    }
+
+    fn schedule(&mut self, event: Event) {
+        let due = self.rows_count + self.rng.random_range(1..=MAX_DISPUTE_DELAY);
+        self.events_count += 1;
+        self.pending.push(Reverse((due, self.events_count, event)));
+    }
+
+    fn next_event(&mut self) -> Option<GeneratedRecord> {
+        while let Some(Reverse((due, _, _))) = self.pending.peek() {
+            if *due > self.rows_count {
+                return None;
+            }
+            let Reverse((_, _, event)) = self.pending.pop().expect("event should be pending");
+            match event {
+                Event::Dispute { client, tx, cents } => {
+                    let amount = Self::amount(cents);
+                    let state = &mut self.clients[usize::from(client - 1)];
+                    // the funds may have been withdrawn in the meantime
+                    if state.locked || state.available < amount {
+                        continue;
+                    }
+                    state.available -= amount;
+                    let chargeback = self.rng.random_bool(self.config.chargeback_rate);
+                    self.schedule(Event::Resolve {
+                        client,
+                        tx,
+                        cents,
+                        chargeback,
+                    });
+                    return Some(Self::reference(InputFileRecordType::Dispute, client, tx));
+                }
+                Event::Resolve {
+                    client,
+                    tx,
+                    cents,
+                    chargeback,
+                } => {
+                    // resolves apply to locked clients as well
+                    self.clients[usize::from(client - 1)].available += Self::amount(cents);
+                    if chargeback {
+                        self.schedule(Event::Chargeback { client, tx, cents });
+                    }
+                    return Some(Self::reference(InputFileRecordType::Resolve, client, tx));
+                }
+                Event::Chargeback { client, tx, cents } => {
+                    let amount = Self::amount(cents);
+                    let state = &mut self.clients[usize::from(client - 1)];
+                    if state.locked || state.available < amount {
+                        continue;
+                    }
+                    state.available -= amount;
+                    state.locked = true;
+                    let index = self
+                        .unlocked
+                        .iter()
+                        .position(|unlocked| *unlocked == client)
+                        .expect("client should be unlocked");
+                    self.unlocked.swap_remove(index);
+                    return Some(Self::reference(InputFileRecordType::Chargeback, client, tx));
+                }
+            }
+        }
+        None
+    }
+
+    /// Deposits are whole cents, so that the same amount is computed for the dispute
+    fn amount(cents: u64) -> f64 {
+        cents as f64 / 100.0
+    }
+
+    fn reference(r#type: InputFileRecordType, client: u16, tx: u64) -> GeneratedRecord {
+        GeneratedRecord {
+            r#type,
+            client,
+            tx,
+            amount: None,
+        }
+    }
+
+    fn transfer(&mut self) -> GeneratedRecord {
+        // once every client is locked the rows are ignored by the engine, but still generated
+        let client = if self.unlocked.is_empty() {
+            self.rng.random_range(1..=self.config.clients)
+        } else {
+            self.unlocked[self.rng.random_range(0..self.unlocked.len())]
+        };
+        let tx = self.next_tx();
+        let state = &mut self.clients[usize::from(client - 1)];
+        if state.available >= 0.01 && self.rng.random_bool(self.config.withdrawal_ratio) {
+            // rounded down, so that the amount never exceeds the available funds
+            let amount = (self.rng.random_range(0.0..1.0) * state.available * 1e4).floor() / 1e4;
+            state.available -= amount;
+            return GeneratedRecord {
+                r#type: InputFileRecordType::Withdrawal,
+                client,
+                tx,
+                amount: Some(amount),
+            };
+        }
+        let cents = self.rng.random_range(100..100_000);
+        let amount = Self::amount(cents);
+        state.available += amount;
+        if self.rng.random_bool(self.config.dispute_rate) {
+            self.schedule(Event::Dispute { client, tx, cents });
+        }
+        GeneratedRecord {
+            r#type: InputFileRecordType::Deposit,
+            client,
+            tx,
+            amount: Some(amount),
+        }
+    }
+
+    fn malformed(&mut self) -> MalformedRecord {
+        let client = self.rng.random_range(1..=self.config.clients).to_string();
+        let tx = self.next_tx().to_string();
+        match self.rng.random_range(0..3) {
+            0 => MalformedRecord {
+                r#type: "refund",
+                client,
+                tx,
+                amount: "10.0".to_string(),
+            },
+            1 => MalformedRecord {
+                r#type: "deposit",
+                client: format!("client-{client}"),
+                tx,
+                amount: "10.0".to_string(),
+            },
+            _ => MalformedRecord {
+                r#type: "deposit",
+                client,
+                tx,
+                amount: "10,0".to_string(),
+            },
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = GeneratedRow;
+
+    fn next(&mut self) -> Option<GeneratedRow> {
+        if self.rows_count == self.config.transactions {
+            return None;
+        }
+        let row = if self.rng.random_bool(self.config.malformed_rate) {
+            GeneratedRow::Malformed(self.malformed())
+        } else if let Some(record) = self.next_event() {
+            GeneratedRow::Record(record)
+        } else {
+            GeneratedRow::Record(self.transfer())
+        };
+        self.rows_count += 1;
+        Some(row)
+    }
+}
+
+pub fn write_rows<W: Write>(
+    writer: W,
+    format: Format,
+    rows: impl Iterator<Item = GeneratedRow>,
+) -> anyhow::Result<()> {
+    match format {
+        Format::Csv => {
+            let mut wtr = csv::Writer::from_writer(writer);
+            for row in rows {
+                wtr.serialize(row)?;
+            }
+            wtr.flush()?;
+        }
+        Format::Jsonl => {
+            let mut wtr = BufWriter::new(writer);
+            for row in rows {
+                serde_json::to_writer(&mut wtr, &row)?;
+                writeln!(wtr)?;
+            }
+            wtr.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input_file_reader::InputFileReader, service::Service};
+
+    fn config(seed: u64) -> GeneratorConfig {
+        GeneratorConfig {
+            clients: 500,
+            transactions: 5_000,
+            withdrawal_ratio: 0.4,
+            dispute_rate: 0.2,
+            chargeback_rate: 0.3,
+            malformed_rate: 0.0,
+            seed,
+        }
+    }
+
+    fn generate_csv(config: GeneratorConfig) -> Vec<u8> {
+        let mut output = Vec::new();
+        let generator = Generator::new(config).expect("config should be valid");
+        write_rows(&mut output, Format::Csv, generator).expect("rows should be written");
+        output
+    }
+
+    #[tokio::test]
+    async fn generated_records_apply() {
+        let records = InputFileReader::parse_strict(generate_csv(config(7)).as_slice())
+            .expect("header should parse");
+        assert_eq!(records.len(), 5_000);
+        let mut service = Service::new();
+        let mut counts = [0; 3];
+        for (line, record) in records {
+            let record = record.expect("record should parse");
+            match record.r#type {
+                InputFileRecordType::Dispute => counts[0] += 1,
+                InputFileRecordType::Resolve => counts[1] += 1,
+                InputFileRecordType::Chargeback => counts[2] += 1,
+                _ => {}
+            }
+            if let Err(err) = service.handle_strict(&record).await {
+                panic!("line {line} should apply: {err}");
+            }
+        }
+        assert!(counts.iter().all(|count| *count > 0), "{counts:?}");
+    }
+
+    #[test]
+    fn seeded_with_malformed_rows() {
+        let config = GeneratorConfig {
+            malformed_rate: 0.1,
+            ..config(11)
+        };
+        let malformed = Generator::new(config.clone())
+            .unwrap()
+            .filter(|row| matches!(row, GeneratedRow::Malformed(_)))
+            .count();
+        assert!(malformed > 0);
+        let output = generate_csv(config.clone());
+        assert_eq!(output, generate_csv(config));
+        let records = InputFileReader::parse_strict(output.as_slice()).unwrap();
+        let failed = records.iter().filter(|(_, record)| record.is_err()).count();
+        assert_eq!(failed, malformed);
+    }
+}
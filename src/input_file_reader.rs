@@ -1,8 +1,11 @@
-use anyhow::Context;
-use csv::{Reader, ReaderBuilder, Trim};
+use crate::record_parser::RecordParser;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use csv::{ByteRecord, Reader, ReaderBuilder, StringRecord};
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
-use std::{cmp::PartialEq, fs::File};
+use std::{cmp::PartialEq, fs::File, io::Read};
+use tracing::{info_span, Span};
 
 #[derive(Debug, Display, Deserialize, Copy, Clone, Serialize, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -12,14 +15,37 @@ pub enum InputFileRecordType {
     Dispute,
     Resolve,
     Chargeback,
+    Reversal,
+    Authorize,
+    Capture,
+    Void,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct InputFileRecord {
     pub r#type: InputFileRecordType,
     pub client: u64,
     pub tx: u64,
+    /// Captures without amount capture the whole authorization
     pub amount: Option<f64>,
+    /// Optional event time in RFC 3339 format
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Operator supplied reason, required for reversals
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl InputFileRecord {
+    /// Span the record is handled and reported in, logs of a transaction are found by its fields
+    pub fn span(&self) -> Span {
+        info_span!(
+            "transaction",
+            tx = self.tx,
+            client = self.client,
+            r#type = %self.r#type
+        )
+    }
 }
 
 pub struct InputFileReader {
@@ -31,16 +57,136 @@ impl InputFileReader {
         Self { path }
     }
 
+    /// Records are read as the iterator advances, records that fail to parse are skipped
     pub fn read_file(&self) -> anyhow::Result<impl Iterator<Item = InputFileRecord>> {
-        let mut rdr: Reader<File> = ReaderBuilder::new()
-            .flexible(true)
-            .trim(Trim::All)
-            .from_path(self.path.clone())
-            .context(format!("failed to open file: {}", self.path))?;
-        Ok(rdr
-            .deserialize()
-            .filter_map(|record| record.ok())
-            .collect::<Vec<InputFileRecord>>()
-            .into_iter())
+        // a header that fails to read fails every record
+        Ok(Self::records(self.open()?)
+            .ok()
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, record)| record.ok()))
+    }
+
+    /// Every record with its line number, records that fail to parse are kept as errors
+    pub fn read_file_strict(&self) -> anyhow::Result<Vec<(u64, anyhow::Result<InputFileRecord>)>> {
+        Self::parse_strict(self.open()?).context(format!("failed to read header: {}", self.path))
+    }
+
+    fn open(&self) -> anyhow::Result<File> {
+        File::open(&self.path).context(format!("failed to open file: {}", self.path))
+    }
+
+    /// Records that parse, the others are skipped
+    pub fn parse<R: Read>(reader: R) -> Vec<InputFileRecord> {
+        Self::records(reader)
+            .ok()
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, record)| record.ok())
+            .collect()
+    }
+
+    /// Every record with its line number, records that fail to parse are kept as errors
+    pub fn parse_strict<R: Read>(
+        reader: R,
+    ) -> anyhow::Result<Vec<(u64, anyhow::Result<InputFileRecord>)>> {
+        Ok(Self::records(reader)?.collect())
+    }
+
+    /// Records with their line numbers, as they are read.
+    ///
+    /// Rows are read into one reused byte record and parsed by hand, rows the parser gives up on
+    /// are trimmed and deserialized with serde as string records, which decides their result and
+    /// error. Fields are trimmed as they are parsed, trimming every record up front costs more
+    /// than reading it
+    fn records<R: Read>(
+        reader: R,
+    ) -> anyhow::Result<impl Iterator<Item = (u64, anyhow::Result<InputFileRecord>)>> {
+        let mut rdr = Self::reader(reader);
+        // unlike records, the header is validated before it is trimmed
+        let byte_headers = rdr.byte_headers()?;
+        let mut headers = StringRecord::from_byte_record(byte_headers.clone())
+            .map_err(|err| Self::utf8_error(byte_headers, err.utf8_error()))?;
+        headers.trim();
+        let parser = RecordParser::new(&headers);
+        let mut record = ByteRecord::new();
+        let mut index = 0;
+        Ok(std::iter::from_fn(move || {
+            // the header is on the first line, records that fail to read have no line of their own
+            let unread_line = index + 2;
+            index += 1;
+            let read = match rdr.read_byte_record(&mut record) {
+                Ok(false) => return None,
+                Ok(true) => &record,
+                Err(err) => return Some((unread_line, Err(err.into()))),
+            };
+            let line = read
+                .position()
+                .map_or(unread_line, |position| position.line());
+            if let Some(parsed) = parser.as_ref().and_then(|parser| parser.parse(read)) {
+                return Some((line, Ok(parsed)));
+            }
+            Some(match Self::string_record(read) {
+                Ok(string_record) => (
+                    line,
+                    string_record
+                        .deserialize(Some(&headers))
+                        .map_err(Into::into),
+                ),
+                Err(err) => (unread_line, Err(err)),
+            })
+        }))
+    }
+
+    /// The record as a trimmed string record, validated and trimmed the way the reader does
+    fn string_record(record: &ByteRecord) -> anyhow::Result<StringRecord> {
+        let mut trimmed = record.clone();
+        trimmed.trim();
+        let mut record = StringRecord::from_byte_record(trimmed)
+            .map_err(|err| Self::utf8_error(record, err.utf8_error()))?;
+        // trims Unicode whitespace as well
+        record.trim();
+        Ok(record)
+    }
+
+    /// Worded as the error of the string record reader
+    fn utf8_error(record: &ByteRecord, err: &csv::Utf8Error) -> anyhow::Error {
+        match record.position() {
+            Some(position) => anyhow!(
+                "CSV parse error: record {} (line {}, field: {}, byte: {}): {}",
+                position.record(),
+                position.line(),
+                err.field(),
+                position.byte(),
+                err
+            ),
+            None => anyhow!("CSV parse error: field {}: {}", err.field(), err),
+        }
+    }
+
+    fn reader<R: Read>(reader: R) -> Reader<R> {
+        ReaderBuilder::new().flexible(true).from_reader(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_optional_timestamps() {
+        let records = InputFileReader::new("tests/assets/transactions_timestamped.csv".to_string())
+            .read_file()
+            .expect("file should be readable")
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 5);
+        assert_eq!(
+            records[0].timestamp,
+            Some("2024-01-01T09:00:00Z".parse().unwrap())
+        );
+        assert_eq!(records[2].timestamp, None);
+        assert_eq!(records[3].r#type, InputFileRecordType::Dispute);
+        assert_eq!(records[3].amount, None);
+        assert!(records[3].timestamp.is_some());
     }
 }
@@ -0,0 +1,187 @@
+use crate::{client_id::ClientId, transaction_id::TransactionId};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateTier {
+    pub min_balance: f64,
+    pub annual_rate: f64,
+}
+
+/// Rate tiers that apply from `effective_from` until the next table takes over
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateTable {
+    pub effective_from: NaiveDate,
+    pub tiers: Vec<RateTier>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InterestConfig {
+    pub day_count_basis: u16,
+    pub rates: Vec<RateTable>,
+}
+
+impl Default for InterestConfig {
+    fn default() -> Self {
+        Self {
+            day_count_basis: 365,
+            rates: Vec::new(),
+        }
+    }
+}
+
+impl InterestConfig {
+    pub fn annual_rate(&self, date: NaiveDate, balance: f64) -> f64 {
+        self.rates
+            .iter()
+            .filter(|table| table.effective_from <= date)
+            .max_by_key(|table| table.effective_from)
+            .and_then(|table| {
+                table
+                    .tiers
+                    .iter()
+                    .filter(|tier| tier.min_balance <= balance)
+                    .max_by(|a, b| a.min_balance.total_cmp(&b.min_balance))
+            })
+            .map_or(0f64, |tier| tier.annual_rate)
+    }
+
+    pub fn daily_interest(&self, date: NaiveDate, balance: f64) -> InterestAccrual {
+        let annual_rate = self.annual_rate(date, balance);
+        InterestAccrual {
+            date,
+            balance,
+            annual_rate,
+            day_count_basis: self.day_count_basis,
+            amount: balance * annual_rate / self.day_count_basis as f64,
+        }
+    }
+}
+
+/// Calculation basis of a single day of interest
+#[derive(Debug, Copy, Clone)]
+pub struct InterestAccrual {
+    pub date: NaiveDate,
+    pub balance: f64,
+    pub annual_rate: f64,
+    pub day_count_basis: u16,
+    pub amount: f64,
+}
+
+/// System generated deposit crediting the accrued interest to a client
+#[derive(Debug, Clone)]
+pub struct InterestPosting {
+    pub id: TransactionId,
+    pub client: ClientId,
+    pub accruals: Vec<InterestAccrual>,
+}
+
+#[derive(Debug, Default)]
+pub struct InterestLedger {
+    pub last_accrual_date: Option<NaiveDate>,
+    postings: Vec<InterestPosting>,
+}
+
+impl InterestLedger {
+    pub fn postings(&self) -> impl Iterator<Item = &InterestPosting> {
+        self.postings.iter()
+    }
+
+    /// Interest credited to clients by the postings in this ledger
+    pub fn total_paid(&self) -> f64 {
+        self.postings
+            .iter()
+            .flat_map(|posting| &posting.accruals)
+            .map(|accrual| accrual.amount)
+            .sum()
+    }
+
+    pub fn post(&mut self, posting: InterestPosting) {
+        self.postings.push(posting);
+    }
+}
+
+/// Net changes of available balances per client and day, dated by the records that made them.
+/// Catch-up accrual works back from the current balance to the balance at the end of each day
+#[derive(Debug, Default)]
+pub struct BalanceChanges {
+    changes: HashMap<ClientId, BTreeMap<NaiveDate, f64>>,
+}
+
+impl BalanceChanges {
+    pub fn record(&mut self, client_id: ClientId, date: NaiveDate, change: f64) {
+        if change != 0f64 {
+            *self
+                .changes
+                .entry(client_id)
+                .or_default()
+                .entry(date)
+                .or_default() += change;
+        }
+    }
+
+    /// Balance at the end of `date`, given the current one
+    pub fn balance_at(&self, client_id: ClientId, date: NaiveDate, current: f64) -> f64 {
+        let later = self.changes.get(&client_id).map_or(0f64, |changes| {
+            changes
+                .range((Bound::Excluded(date), Bound::Unbounded))
+                .map(|(_, change)| change)
+                .sum()
+        });
+        current - later
+    }
+
+    /// Drops the changes made up to `date`, accrual never goes back that far again
+    pub fn accrued(&mut self, date: NaiveDate) {
+        self.changes.retain(|_, changes| {
+            *changes = changes.split_off(&date.succ_opt().unwrap_or(date));
+            !changes.is_empty()
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, NaiveDate, f64)> + '_ {
+        self.changes.iter().flat_map(|(client_id, changes)| {
+            changes
+                .iter()
+                .map(|(date, change)| (*client_id, *date, *change))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_lookup_by_date_and_balance() {
+        let config: InterestConfig = toml::from_str(
+            r#"
+            day_count_basis = 360
+            rates = [
+                { effective_from = "2024-01-01", tiers = [
+                    { min_balance = 0.0, annual_rate = 0.01 },
+                    { min_balance = 1000.0, annual_rate = 0.02 },
+                ] },
+                { effective_from = "2024-07-01", tiers = [
+                    { min_balance = 0.0, annual_rate = 0.036 },
+                ] },
+            ]
+            "#,
+        )
+        .expect("interest config should parse");
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        assert_eq!(config.annual_rate(date("2023-12-31"), 500.0), 0.0);
+        assert_eq!(config.annual_rate(date("2024-03-01"), 500.0), 0.01);
+        assert_eq!(config.annual_rate(date("2024-03-01"), 5000.0), 0.02);
+        assert_eq!(config.annual_rate(date("2024-07-01"), 5000.0), 0.036);
+        let accrual = config.daily_interest(date("2024-07-02"), 1000.0);
+        assert!((accrual.amount - 0.1).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::{
+    client_id::ClientId,
+    client_info::ClientInfo,
+    client_table::ClientTable,
+    dispute_info::DisputeInfo,
+    service::MAX_AMOUNT,
+    transaction_id::TransactionId,
+    transaction_info::{TransactionStatus, TransactionType},
+    transaction_store::TransactionStore,
+};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Amounts are floats, differences below this are rounding
+pub const TOLERANCE: f64 = 1e-6;
+
+/// Rounding allowed when comparing amounts of the given magnitude
+pub fn tolerance(magnitude: f64) -> f64 {
+    TOLERANCE * magnitude.abs().max(1.0)
+}
+
+#[derive(Debug, Display, Deserialize, Copy, Clone, Default, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum InvariantCheck {
+    #[default]
+    Off,
+    /// After every handled record, scans the whole state so it is meant for debugging
+    PerTransaction,
+    EndOfRun,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InvariantConfig {
+    pub check: InvariantCheck,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InvariantViolation {
+    pub invariant: &'static str,
+    /// Record after which the check ran, or "end of run"
+    pub checked_after: String,
+    pub client: Option<u16>,
+    pub tx: Option<u64>,
+    pub detail: String,
+}
+
+/// Money entering and leaving client accounts during the run, on top of the opening balance.
+/// Fees and interest are moves between client accounts and the bank and are taken from the
+/// revenue account and the interest ledger instead
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MoneyFlows {
+    /// Client balances and fee revenue when the run started
+    pub opening: f64,
+    pub deposits: f64,
+    pub withdrawals: f64,
+    pub chargebacks: f64,
+    pub captures: f64,
+}
+
+impl MoneyFlows {
+    pub fn expected_total(&self) -> f64 {
+        self.opening + self.deposits - self.withdrawals - self.chargebacks - self.captures
+    }
+}
+
+/// Money entering and leaving one client account, tracked apart from its balances
+#[derive(Debug, Copy, Clone)]
+pub struct ClientFlow {
+    /// Opening balance plus everything credited minus everything debited
+    pub net: f64,
+    /// Largest amount moved, f64 rounding left in the balances grows with it
+    pub scale: f64,
+}
+
+impl Default for ClientFlow {
+    fn default() -> Self {
+        Self {
+            net: 0f64,
+            scale: 1f64,
+        }
+    }
+}
+
+/// Flows of every client account, indexed directly by client id like the client table
+#[derive(Debug, Clone, Default)]
+pub struct ClientFlows {
+    slots: Vec<ClientFlow>,
+}
+
+impl ClientFlows {
+    pub fn get(&self, client_id: ClientId) -> ClientFlow {
+        self.slots
+            .get(usize::from(client_id.value()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn slot(&mut self, client_id: ClientId) -> &mut ClientFlow {
+        let index = usize::from(client_id.value());
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, ClientFlow::default);
+        }
+        &mut self.slots[index]
+    }
+
+    /// Money entering (positive) or leaving (negative) the client account
+    pub fn record(&mut self, client_id: ClientId, amount: f64) {
+        let flow = self.slot(client_id);
+        flow.net += amount;
+        flow.scale = flow.scale.max(amount.abs().min(MAX_AMOUNT));
+    }
+
+    /// Money moved within the client account, between available and held
+    pub fn moved(&mut self, client_id: ClientId, amount: f64) {
+        let flow = self.slot(client_id);
+        flow.scale = flow.scale.max(amount.abs().min(MAX_AMOUNT));
+    }
+}
+
+/// Engine state the invariants are checked against
+pub struct Ledger<'a> {
+    pub clients: &'a ClientTable,
+    pub client_flows: &'a ClientFlows,
+    pub transactions: &'a TransactionStore,
+    pub disputes: &'a HashMap<TransactionId, DisputeInfo>,
+    pub flows: MoneyFlows,
+    pub fee_revenue: f64,
+    pub interest_paid: f64,
+}
+
+struct Violation {
+    invariant: &'static str,
+    client: Option<ClientId>,
+    tx: Option<TransactionId>,
+    detail: String,
+}
+
+impl Ledger<'_> {
+    /// Checks that client totals match the money credited to and debited from each account,
+    /// held funds are non-negative and match open disputes and authorizations, open disputes
+    /// reference a successful deposit of the same client, and client balances plus fee revenue
+    /// match the opening balance and the money deposited, withdrawn, charged back, captured and
+    /// paid as interest
+    pub fn check(&self, checked_after: &str) -> Vec<InvariantViolation> {
+        let mut violations = Vec::new();
+        self.check_clients(&mut violations);
+        self.check_disputes(&mut violations);
+        self.check_flows(&mut violations);
+        violations
+            .into_iter()
+            .map(|violation| InvariantViolation {
+                invariant: violation.invariant,
+                checked_after: checked_after.to_string(),
+                client: violation.client.map(|client| client.value()),
+                tx: violation.tx.map(|tx| tx.value()),
+                detail: violation.detail,
+            })
+            .collect()
+    }
+
+    fn check_clients(&self, violations: &mut Vec<Violation>) {
+        let mut disputed = HashMap::<ClientId, f64>::new();
+        for (transaction_id, dispute) in self.disputes {
+            let amount = self
+                .transactions
+                .get(transaction_id)
+                .map(|info| info.amount)
+                .unwrap_or_default();
+            *disputed.entry(dispute.client).or_default() += amount;
+        }
+        for (client_id, info) in self.clients.iter() {
+            let mut violation = |invariant, detail| {
+                violations.push(Violation {
+                    invariant,
+                    client: Some(client_id),
+                    tx: None,
+                    detail,
+                })
+            };
+            let flow = self.client_flows.get(client_id);
+            let tolerance = TOLERANCE * flow.scale;
+            // the reported total, against the money that entered and left the account
+            let total = info.total();
+            let off = (total - flow.net).abs();
+            if off > tolerance || off.is_nan() {
+                violation(
+                    "total_matches_net_flow",
+                    format!(
+                        "total: {total}, available: {}, held: {}, net flow: {}",
+                        info.available, info.on_hold, flow.net
+                    ),
+                );
+            }
+            if info.on_hold < -tolerance || info.authorized < -tolerance {
+                violation(
+                    "non_negative_held",
+                    format!("held: {}, authorized: {}", info.on_hold, info.authorized),
+                );
+            }
+            let disputed = disputed.get(&client_id).copied().unwrap_or_default();
+            if (info.on_hold - info.authorized - disputed).abs() > tolerance {
+                violation(
+                    "held_matches_disputes_and_authorizations",
+                    format!(
+                        "held: {}, authorized: {}, disputed: {disputed}",
+                        info.on_hold, info.authorized
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_disputes(&self, violations: &mut Vec<Violation>) {
+        let mut transaction_ids = self.disputes.keys().copied().collect::<Vec<_>>();
+        transaction_ids.sort();
+        for transaction_id in transaction_ids {
+            let dispute = &self.disputes[&transaction_id];
+            let detail = match self.transactions.get(&transaction_id) {
+                None => "disputed transaction not found".to_string(),
+                Some(info) if info.r#type != TransactionType::Deposit => {
+                    format!("disputed transaction is a {}", info.r#type)
+                }
+                Some(info) if info.status != TransactionStatus::Success => {
+                    format!("disputed deposit has status {}", info.status)
+                }
+                Some(info) if info.client != dispute.client => {
+                    format!("disputed deposit belongs to client {}", info.client)
+                }
+                Some(_) => continue,
+            };
+            violations.push(Violation {
+                invariant: "dispute_references_successful_deposit",
+                client: Some(dispute.client),
+                tx: Some(transaction_id),
+                detail,
+            });
+        }
+    }
+
+    fn check_flows(&self, violations: &mut Vec<Violation>) {
+        let balances = self.clients.values().map(ClientInfo::total).sum::<f64>();
+        let actual = balances + self.fee_revenue - self.interest_paid;
+        let expected = self.flows.expected_total();
+        if (actual - expected).abs() > tolerance(expected) {
+            violations.push(Violation {
+                invariant: "balances_match_money_flows",
+                client: None,
+                tx: None,
+                detail: format!(
+                    "client balances: {balances}, fee revenue: {}, interest paid: {}, expected from flows: {expected} ({:?})",
+                    self.fee_revenue, self.interest_paid, self.flows
+                ),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction_info::TransactionInfo;
+    use chrono::Utc;
+
+    #[test]
+    fn reports_violations_with_context() {
+        let client = ClientId::new(1);
+        let clients = ClientTable::from_iter([(
+            client,
+            ClientInfo {
+                on_hold: -1.0,
+                available: 11.0,
+                authorized: 0.0,
+                is_locked: false,
+            },
+        )]);
+        let transactions = TransactionStore::from_iter([(
+            TransactionId::new(7),
+            TransactionInfo {
+                r#type: TransactionType::Withdrawal,
+                client,
+                amount: 5.0,
+                status: TransactionStatus::Success,
+                timestamp: None,
+            },
+        )]);
+        let disputes = HashMap::from([(
+            TransactionId::new(7),
+            DisputeInfo {
+                client,
+                opened_at: Utc::now(),
+            },
+        )]);
+        let mut client_flows = ClientFlows::default();
+        client_flows.record(client, 10.0);
+        let ledger = Ledger {
+            clients: &clients,
+            client_flows: &client_flows,
+            transactions: &transactions,
+            disputes: &disputes,
+            flows: MoneyFlows {
+                deposits: 10.0,
+                ..Default::default()
+            },
+            fee_revenue: 0.0,
+            interest_paid: 0.0,
+        };
+        let violations = ledger.check("tx 7");
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.invariant)
+                .collect::<Vec<_>>(),
+            vec![
+                "non_negative_held",
+                "held_matches_disputes_and_authorizations",
+                "dispute_references_successful_deposit",
+            ]
+        );
+        assert_eq!(violations[2].tx, Some(7));
+        assert_eq!(violations[2].checked_after, "tx 7");
+    }
+
+    #[test]
+    fn reports_totals_off_the_net_flow() {
+        let client = ClientId::new(1);
+        let other = ClientId::new(2);
+        let info = |available, on_hold| ClientInfo {
+            on_hold,
+            available,
+            authorized: 0.0,
+            is_locked: false,
+        };
+        let clients = ClientTable::from_iter([(client, info(9.5, 1.0)), (other, info(1e6, 0.0))]);
+        let mut client_flows = ClientFlows::default();
+        client_flows.record(client, 10.0);
+        // rounding within the tolerance of the largest amount moved is not reported
+        client_flows.record(other, 1e6 + 1e-4);
+        client_flows.moved(other, 1.0);
+        let ledger = Ledger {
+            clients: &clients,
+            client_flows: &client_flows,
+            transactions: &TransactionStore::default(),
+            disputes: &HashMap::new(),
+            flows: MoneyFlows {
+                deposits: 10.5 + 1e6,
+                ..Default::default()
+            },
+            fee_revenue: 0.0,
+            interest_paid: 0.0,
+        };
+        let violations = ledger.check("tx 1");
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| (violation.invariant, violation.client))
+                .collect::<Vec<_>>(),
+            vec![
+                ("total_matches_net_flow", Some(1)),
+                ("held_matches_disputes_and_authorizations", Some(1)),
+            ]
+        );
+        assert_eq!(
+            violations[0].detail,
+            "total: 10.5, available: 9.5, held: 1, net flow: 10"
+        );
+    }
+}
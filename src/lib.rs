@@ -0,0 +1,42 @@
+//! Transaction engine behind the command-line tool, shared with the fuzz targets
+pub mod audit_log;
+pub mod authorization_info;
+pub mod authorization_policy;
+pub mod batch;
+pub mod client_config;
+pub mod client_id;
+pub mod client_info;
+pub mod client_table;
+pub mod clock;
+pub mod config;
+pub mod dispute_info;
+pub mod dispute_policy;
+pub mod expiry_queue;
+pub mod fee_schedule;
+pub mod fraud_rules;
+pub mod generator;
+pub mod input_file_reader;
+pub mod interest;
+pub mod invariants;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod output_record;
+pub mod output_writer;
+pub mod pipeline;
+pub mod record_parser;
+pub mod reorder_buffer;
+pub mod revenue_account;
+pub mod reversal_info;
+pub mod run_summary;
+pub mod scheduler;
+pub mod service;
+#[cfg(test)]
+mod service_model;
+#[cfg(test)]
+mod shared_buffer;
+pub mod snapshot;
+pub mod transaction_id;
+pub mod transaction_info;
+pub mod transaction_store;
+pub mod validation;
@@ -0,0 +1,150 @@
+use crate::client_id::ClientId;
+use chrono::{DateTime, Duration, Utc};
+use derive_more::Display;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+
+/// Withdrawal limits, unset fields are not enforced
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WithdrawalLimits {
+    pub max_withdrawals: Option<u32>,
+    pub max_withdrawal_total: Option<f64>,
+    pub max_single_withdrawal: Option<f64>,
+}
+
+impl WithdrawalLimits {
+    /// Fields set in `self` take precedence over `defaults`
+    pub fn or(self, defaults: WithdrawalLimits) -> WithdrawalLimits {
+        WithdrawalLimits {
+            max_withdrawals: self.max_withdrawals.or(defaults.max_withdrawals),
+            max_withdrawal_total: self.max_withdrawal_total.or(defaults.max_withdrawal_total),
+            max_single_withdrawal: self
+                .max_single_withdrawal
+                .or(defaults.max_single_withdrawal),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub window_hours: u32,
+    pub max_withdrawals: Option<u32>,
+    pub max_withdrawal_total: Option<f64>,
+    pub max_single_withdrawal: Option<f64>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            window_hours: 24,
+            max_withdrawals: None,
+            max_withdrawal_total: None,
+            max_single_withdrawal: None,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn defaults(&self) -> WithdrawalLimits {
+        WithdrawalLimits {
+            max_withdrawals: self.max_withdrawals,
+            max_withdrawal_total: self.max_withdrawal_total,
+            max_single_withdrawal: self.max_single_withdrawal,
+        }
+    }
+}
+
+#[derive(Debug, Display, Copy, Clone, PartialEq)]
+pub enum LimitViolation {
+    #[display("single withdrawal limit exceeded, limit: {limit}, requested: {amount}")]
+    SingleWithdrawal { limit: f64, amount: f64 },
+    #[display("withdrawal count limit exceeded, limit: {limit} per {window_hours}h")]
+    WithdrawalCount { limit: u32, window_hours: u32 },
+    #[display(
+        "withdrawal total limit exceeded, limit: {limit} per {window_hours}h, withdrawn: {withdrawn}, requested: {amount}"
+    )]
+    WithdrawalTotal {
+        limit: f64,
+        window_hours: u32,
+        withdrawn: f64,
+        amount: f64,
+    },
+}
+
+impl std::error::Error for LimitViolation {}
+
+/// Successful withdrawals per client within the rolling window
+#[derive(Debug)]
+pub struct VelocityTracker {
+    window_hours: u32,
+    withdrawals: HashMap<ClientId, VecDeque<(DateTime<Utc>, f64)>>,
+}
+
+impl VelocityTracker {
+    pub fn new(window_hours: u32) -> VelocityTracker {
+        Self {
+            window_hours,
+            withdrawals: Default::default(),
+        }
+    }
+
+    pub fn check(
+        &mut self,
+        client_id: ClientId,
+        amount: f64,
+        now: DateTime<Utc>,
+        limits: WithdrawalLimits,
+    ) -> Result<(), LimitViolation> {
+        if let Some(limit) = limits.max_single_withdrawal {
+            if amount > limit {
+                return Err(LimitViolation::SingleWithdrawal { limit, amount });
+            }
+        }
+        let window_start = now - Duration::hours(self.window_hours as i64);
+        let window = self.withdrawals.entry(client_id).or_default();
+        while window
+            .front()
+            .is_some_and(|(time, _)| *time <= window_start)
+        {
+            window.pop_front();
+        }
+        if let Some(limit) = limits.max_withdrawals {
+            if window.len() >= limit as usize {
+                return Err(LimitViolation::WithdrawalCount {
+                    limit,
+                    window_hours: self.window_hours,
+                });
+            }
+        }
+        if let Some(limit) = limits.max_withdrawal_total {
+            let withdrawn = window.iter().map(|(_, amount)| amount).sum::<f64>();
+            if withdrawn + amount > limit {
+                return Err(LimitViolation::WithdrawalTotal {
+                    limit,
+                    window_hours: self.window_hours,
+                    withdrawn,
+                    amount,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, client_id: ClientId, amount: f64, time: DateTime<Utc>) {
+        self.withdrawals
+            .entry(client_id)
+            .or_default()
+            .push_back((time, amount));
+    }
+
+    /// Recorded withdrawals per client in time order, including those the window has moved past
+    /// but were not dropped yet
+    pub fn withdrawals(&self) -> impl Iterator<Item = (ClientId, DateTime<Utc>, f64)> + '_ {
+        self.withdrawals.iter().flat_map(|(client_id, window)| {
+            window
+                .iter()
+                .map(|(time, amount)| (*client_id, *time, *amount))
+        })
+    }
+}
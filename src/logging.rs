@@ -0,0 +1,96 @@
+//! Structured logs on stderr, filtered by `RUST_LOG` (errors only by default) and written as text
+//! or, with `LOG_FORMAT=json`, as one JSON object per line.
+//!
+//! Every transaction record is handled in a `transaction` span carrying its `tx`, `client` and
+//! `type`, with a child span per `process_*` step. JSON lines list the spans they were logged in,
+//! outermost first, so the lines of a single transaction are the ones whose first span has its
+//! `tx`. The spans are recorded from `RUST_LOG=info` on, `process_*` spans from `debug` on
+use tracing::Subscriber;
+use tracing_subscriber::{fmt::MakeWriter, util::SubscriberInitExt, EnvFilter};
+
+/// Installs the global subscriber, logs of dependencies using `log` are forwarded to it
+pub fn init() {
+    let filter = EnvFilter::from_default_env();
+    let json = std::env::var("LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json"));
+    if json {
+        json_subscriber(filter, std::io::stderr).init();
+    } else {
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr)
+            .init();
+    }
+}
+
+/// Events with the list of spans they happened in
+pub fn json_subscriber<W>(filter: EnvFilter, writer: W) -> impl Subscriber + Send + Sync
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt()
+        .json()
+        .with_current_span(false)
+        .with_span_list(true)
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input_file_reader::{InputFileRecord, InputFileRecordType},
+        service::{Service, TransactionRecordHandler},
+        shared_buffer::SharedBuffer,
+    };
+    use serde_json::Value;
+
+    fn record(r#type: InputFileRecordType, tx: u64, amount: f64) -> InputFileRecord {
+        InputFileRecord {
+            r#type,
+            client: 7,
+            tx,
+            amount: Some(amount),
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn events_carry_the_transaction_span() {
+        let buffer = SharedBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = json_subscriber(EnvFilter::new("debug"), move || writer.clone());
+        let _default = tracing::subscriber::set_default(subscriber);
+        let mut service = Service::new();
+        for record in [
+            record(InputFileRecordType::Deposit, 1, 1.0),
+            record(InputFileRecordType::Withdrawal, 2, 5.0),
+        ] {
+            let _ = service.handle(&record).await;
+        }
+
+        let events = buffer
+            .contents()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).expect("line should be JSON"))
+            .filter(|event| event["spans"][0]["tx"] == 2)
+            .collect::<Vec<_>>();
+        assert!(events.iter().all(|event| event["spans"][0]["client"] == 7));
+        assert_eq!(events[0]["spans"][0]["type"], "Withdrawal");
+        assert_eq!(events[0]["fields"]["message"], "handling transaction");
+        // the funds check fails inside the withdrawal step of the transaction
+        let failure = events
+            .iter()
+            .find(|event| event["level"] == "ERROR")
+            .expect("withdrawal should fail");
+        let spans = failure["spans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|span| span["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["transaction", "process_withdrawal"]);
+    }
+}
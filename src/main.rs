@@ -1,56 +1,323 @@
-mod client_id;
-mod client_info;
-mod input_file_reader;
-mod output_record;
-mod output_writer;
-mod service;
-mod transaction_id;
-mod transaction_info;
-
-use crate::{
+mod cli;
+
+use crate::cli::{Cli, Command, EngineArgs, RunArgs};
+use anyhow::Context;
+use bank::{
+    audit_log::{self, AuditLog},
+    batch::apply_batch,
+    client_config::ClientConfigReader,
+    config::Config,
     input_file_reader::InputFileReader,
+    logging,
+    metrics::{self, Metrics},
     output_record::OutputRecordProvider,
     output_writer::OutputWriter,
-    service::{Service, TransactionRecordHandler},
+    pipeline::Pipeline,
+    reorder_buffer::EventTimeProcessor,
+    scheduler::ScheduleReader,
+    service::Service,
+    snapshot::Snapshot,
+    validation::validate,
+};
+use clap::Parser;
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    iter, process,
+    sync::{Arc, Mutex},
 };
-use log::{debug, error};
-use std::{env, io, process};
+use tokio::net::TcpListener;
+use tracing::{debug, error, info, warn};
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    logging::init();
+
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) if err.use_stderr() => {
+            error!("{err}");
+            process::exit(1);
+        }
+        Err(err) => err.exit(),
+    };
+
+    match (cli.command, cli.run) {
+        (None, None) => unreachable!("clap requires either a command or a transactions file"),
+        (None, Some(run)) => {
+            let mut service = process_file(&run).await;
+            write_output(&mut service, &run);
+        }
+        (
+            Some(Command::Accrue {
+                date,
+                interest_report,
+                run,
+            }),
+            _,
+        ) => {
+            let mut service = process_file(&run).await;
+            match service.accrue_interest(date) {
+                Ok(postings_count) => info!("interest posted for {postings_count} clients"),
+                Err(err) => error!("interest accrual failure: {err}"),
+            }
+            write_output(&mut service, &run);
+            if let Some(path) = &interest_report {
+                write_report(path, service.get_interest_records());
+            }
+        }
+        (
+            Some(Command::Validate {
+                transactions_file,
+                engine,
+                error_report,
+            }),
+            _,
+        ) => {
+            let (config, snapshot) = load_engine(&engine);
+            let records = match InputFileReader::new(transactions_file).read_file_strict() {
+                Ok(records) => records,
+                Err(err) => {
+                    error!("{err}");
+                    process::exit(2);
+                }
+            };
+            // the copy is dropped, nothing is saved
+            let mut service = build_service(&config, snapshot.as_ref());
+            let report = validate(&mut service, records).await;
+            print!("{report}");
+            let is_clean = report.is_clean();
+            if let Some(path) = &error_report {
+                write_report(path, report.rejected.into_iter());
+            }
+            if !is_clean {
+                process::exit(3);
+            }
+        }
+        (Some(Command::VerifyAudit { audit_log }), _) => {
+            let verification = File::open(&audit_log)
+                .context(format!("failed to open audit log: {audit_log}"))
+                .and_then(|file| audit_log::verify(BufReader::new(file)));
+            match verification {
+                Ok(verification) => {
+                    println!("{verification}");
+                    if !verification.is_intact() {
+                        process::exit(3);
+                    }
+                }
+                Err(err) => {
+                    error!("{err:#}");
+                    process::exit(2);
+                }
+            }
+        }
+        (
+            Some(Command::RunSchedule {
+                until,
+                schedule,
+                schedule_report,
+                run,
+            }),
+            _,
+        ) => {
+            let instructions = match ScheduleReader::new(schedule).read_file() {
+                Ok(instructions) => instructions,
+                Err(err) => {
+                    error!("{err:#}");
+                    process::exit(1);
+                }
+            };
+            let mut service = process_file(&run).await;
+            let attempts_count = service.run_schedule(&instructions, until).await;
+            info!("{attempts_count} scheduled transactions processed up to {until}");
+            write_output(&mut service, &run);
+            if let Some(path) = &schedule_report {
+                write_report(path, service.get_scheduled_records());
+            }
+        }
+    }
+}
+
+fn load_config(args: &EngineArgs) -> anyhow::Result<Config> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(path) = &args.client_config {
+        config.clients = ClientConfigReader::new(path.to_string()).read_file()?;
+    }
+    Ok(config)
+}
+
+/// Loads the configuration and the snapshot to start from, exits on failure
+fn load_engine(args: &EngineArgs) -> (Config, Option<Snapshot>) {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{err:#}");
+            process::exit(1);
+        }
+    };
+    let snapshot = match args
+        .load_snapshot
+        .as_deref()
+        .map(Snapshot::load)
+        .transpose()
+    {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            error!("{err:#}");
+            process::exit(1);
+        }
+    };
+    (config, snapshot)
+}
 
-    let args: Vec<String> = env::args().collect();
-    let args_count = args.len() - 1;
-    if args_count != 1 {
-        error!(
-            "Expected 1 argument, {args_count} were provided\n\
-                Usage: {} <transactions_file.csv>",
-            args[0]
-        );
-        process::exit(1);
+fn build_service(config: &Config, snapshot: Option<&Snapshot>) -> Service {
+    let mut service = Service::with_config(config.clone());
+    if let Some(snapshot) = snapshot {
+        service.import_snapshot(snapshot.clone());
     }
+    service
+}
 
-    let mut service = Service::new();
-    let transactions_file_path = &args[1];
+async fn process_file(args: &RunArgs) -> Service {
+    let (config, snapshot) = load_engine(&args.engine);
+    let metrics = (args.metrics.is_some() || args.metrics_addr.is_some())
+        .then(|| Arc::new(Metrics::default()));
+    if let (Some(addr), Some(metrics)) = (&args.metrics_addr, &metrics) {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                info!("serving metrics on {addr}");
+                tokio::spawn(metrics::serve(listener, metrics.clone()));
+            }
+            Err(err) => error!("failed to serve metrics on {addr}: {err}"),
+        }
+    }
+    let audit_log = match args.audit_log.as_deref().map(AuditLog::open).transpose() {
+        Ok(audit_log) => audit_log.map(|audit_log| Arc::new(Mutex::new(audit_log))),
+        Err(err) => {
+            error!("{err:#}");
+            process::exit(1);
+        }
+    };
+    let mut service = build_service(&config, snapshot.as_ref());
+    service.set_metrics(metrics);
+    service.set_audit_log(audit_log);
+    let transactions_file_path = &args.transactions_file;
     debug!("Reading file: {transactions_file_path}");
     let file_reader = InputFileReader::new(transactions_file_path.to_string());
-    match file_reader.read_file() {
-        Ok(records) => {
-            for transaction in records {
-                if let Err(err) = service.handle(&transaction).await {
-                    error!("transaction failure: {err}");
-                }
+    if args.batch {
+        let records = match file_reader.read_file_strict() {
+            Ok(records) => records,
+            Err(err) => {
+                error!("{err}");
+                process::exit(2);
             }
+        };
+        if config.reordering.is_some() {
+            warn!("records are applied in file order in batch mode");
+        }
+        let errors = apply_batch(&mut service, records).await;
+        if !errors.is_empty() {
+            error!(
+                "batch rejected with {} failing records, no record of {transactions_file_path} was applied",
+                errors.len()
+            );
         }
+        if let Some(path) = &args.error_report {
+            write_report(path, errors.into_iter());
+        }
+        return service;
+    }
+    let records = match file_reader.read_file() {
+        Ok(records) => records,
         Err(err) => {
             error!("{err}");
             process::exit(2);
         }
+    };
+    let mut pipeline = match Pipeline::new(&config.pipeline) {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+            error!("{err:#}");
+            process::exit(1);
+        }
+    };
+    if let Some(path) = &args.results {
+        match File::create(path) {
+            Ok(file) => pipeline = pipeline.with_results(file),
+            Err(err) => error!("failed to create results file {path}: {err}"),
+        }
+    }
+    let outcome = match &config.reordering {
+        None => pipeline.run(&mut service, records).await,
+        Some(reordering) => {
+            let mut processor = EventTimeProcessor::new(reordering);
+            let outcome = pipeline
+                .run_in_event_time(&mut service, records, &mut processor)
+                .await;
+            if let Some(path) = &args.late_report {
+                write_report(path, processor.late_records());
+            }
+            outcome
+        }
+    };
+    if let Err(err) = outcome {
+        error!("{err:#}");
     }
+    service
+}
 
+fn write_output(service: &mut Service, args: &RunArgs) {
+    service.finish_run();
     let writer = OutputWriter::new();
     if let Err(err) = writer.write(io::stdout(), service.get_records()) {
         error!("failed to write results to output: {err}");
     }
+    info!("run summary: {}", service.run_summary());
+    info!("fee revenue: {}", service.revenue_account().balance());
+    if let Some(path) = &args.save_snapshot {
+        if let Err(err) = service.export_snapshot().save(path) {
+            error!("{err:#}");
+        }
+    }
+    if let Some(path) = &args.summary {
+        write_report(path, iter::once(service.run_summary()));
+    }
+    if let Some(path) = &args.statement {
+        write_report(path, service.get_statement_records());
+    }
+    if let Some(path) = &args.fee_report {
+        write_report(path, service.get_fee_records());
+    }
+    if let Some(path) = &args.flagged_report {
+        write_report(path, service.get_flagged_records());
+    }
+    if let Some(path) = &args.history_report {
+        write_report(path, service.get_history_records());
+    }
+    if let Some(path) = &args.invariant_report {
+        write_report(path, service.invariant_violations());
+    }
+    if let Some(audit_log) = service.audit_log() {
+        if let Err(err) = audit_log.lock().expect("audit log lock poisoned").finish() {
+            error!("{err:#}");
+        }
+    }
+    if let (Some(path), Some(metrics)) = (&args.metrics, service.metrics()) {
+        if let Err(err) = fs::write(path, metrics.render()) {
+            error!("failed to write metrics {path}: {err}");
+        }
+    }
+}
+
+fn write_report<R: Serialize>(path: &str, records: impl Iterator<Item = R>) {
+    if let Err(err) = File::create(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| OutputWriter::new().write(file, records))
+    {
+        error!("failed to write report {path}: {err}");
+    }
 }
@@ -0,0 +1,342 @@
+//! Engine metrics in the Prometheus text format, served over HTTP or written to a file
+use crate::{input_file_reader::InputFileRecordType, pipeline::ResultStatus};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, error};
+
+const RECORD_TYPES: [InputFileRecordType; 9] = [
+    InputFileRecordType::Deposit,
+    InputFileRecordType::Withdrawal,
+    InputFileRecordType::Dispute,
+    InputFileRecordType::Resolve,
+    InputFileRecordType::Chargeback,
+    InputFileRecordType::Reversal,
+    InputFileRecordType::Authorize,
+    InputFileRecordType::Capture,
+    InputFileRecordType::Void,
+];
+
+const OUTCOMES: [ResultStatus; 2] = [ResultStatus::Accepted, ResultStatus::Rejected];
+
+/// Upper bounds of the record latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.1,
+];
+
+/// Counters are updated as records are handled, gauges are refreshed from the engine state
+/// every few thousand records and at the end of the run.
+/// Shared between the engine and the metrics endpoint, and kept across service rebuilds
+#[derive(Debug, Default)]
+pub struct Metrics {
+    records: [[AtomicU64; OUTCOMES.len()]; RECORD_TYPES.len()],
+    /// Error messages up to their first colon, values such as ids and amounts follow it
+    rejections: Mutex<BTreeMap<String, u64>>,
+    /// Not cumulative, the last bucket counts records slower than every bound
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_sum_nanos: AtomicU64,
+    /// Records of batches that were rolled back, counted nowhere else
+    rolled_back: AtomicU64,
+    open_disputes: AtomicU64,
+    locked_clients: AtomicU64,
+    /// Bits of the `f64` total
+    held_funds: AtomicU64,
+}
+
+impl Metrics {
+    /// `rejection` is the error message of a rejected record, see `Service::rejection`
+    pub fn record(&self, r#type: InputFileRecordType, rejection: Option<&str>, latency: Duration) {
+        let status = match rejection {
+            None => ResultStatus::Accepted,
+            Some(message) => {
+                let reason = message.split(':').next().unwrap_or_default().trim();
+                let mut rejections = self.rejections.lock().expect("metrics lock poisoned");
+                *rejections.entry(reason.to_string()).or_default() += 1;
+                ResultStatus::Rejected
+            }
+        };
+        self.records[r#type as usize][status as usize].fetch_add(1, Ordering::Relaxed);
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_nanos
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Adds the counters of `other`, gauges are left alone
+    pub fn merge(&self, other: &Metrics) {
+        for (counts, other_counts) in self.records.iter().zip(&other.records) {
+            for (count, other_count) in counts.iter().zip(other_counts) {
+                count.fetch_add(other_count.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+        }
+        let mut rejections = self.rejections.lock().expect("metrics lock poisoned");
+        for (reason, count) in other
+            .rejections
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+        {
+            *rejections.entry(reason.clone()).or_default() += count;
+        }
+        for (count, other_count) in self.latency_buckets.iter().zip(&other.latency_buckets) {
+            count.fetch_add(other_count.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        self.latency_sum_nanos.fetch_add(
+            other.latency_sum_nanos.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.rolled_back
+            .fetch_add(other.rolled_back.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    pub fn roll_back(&self, records: usize) {
+        self.rolled_back
+            .fetch_add(records as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_state(&self, open_disputes: usize, locked_clients: usize, held_funds: f64) {
+        self.open_disputes
+            .store(open_disputes as u64, Ordering::Relaxed);
+        self.locked_clients
+            .store(locked_clients as u64, Ordering::Relaxed);
+        self.held_funds
+            .store(held_funds.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        Self::header(
+            &mut output,
+            "bank_records_total",
+            "counter",
+            "Records handled by type and outcome",
+        );
+        for (r#type, outcomes) in RECORD_TYPES.iter().zip(&self.records) {
+            for (status, count) in OUTCOMES.iter().zip(outcomes) {
+                let _ = writeln!(
+                    output,
+                    "bank_records_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                    r#type.to_string().to_lowercase(),
+                    status.to_string().to_lowercase(),
+                    count.load(Ordering::Relaxed)
+                );
+            }
+        }
+        Self::header(
+            &mut output,
+            "bank_rejections_total",
+            "counter",
+            "Rejected records by reason",
+        );
+        for (reason, count) in self
+            .rejections
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+        {
+            let _ = writeln!(
+                output,
+                "bank_rejections_total{{reason=\"{}\"}} {count}",
+                escape_label(reason)
+            );
+        }
+        Self::header(
+            &mut output,
+            "bank_record_duration_seconds",
+            "histogram",
+            "Time to handle a record",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                output,
+                "bank_record_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        cumulative += self.latency_buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        let sum = self.latency_sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(
+            output,
+            "bank_record_duration_seconds_bucket{{le=\"+Inf\"}} {cumulative}\n\
+             bank_record_duration_seconds_sum {sum}\n\
+             bank_record_duration_seconds_count {cumulative}"
+        );
+        Self::header(
+            &mut output,
+            "bank_rolled_back_records_total",
+            "counter",
+            "Records of batches that were rolled back",
+        );
+        let _ = writeln!(
+            output,
+            "bank_rolled_back_records_total {}",
+            self.rolled_back.load(Ordering::Relaxed)
+        );
+        for (name, help, value) in [
+            (
+                "bank_open_disputes",
+                "Disputes neither resolved nor charged back",
+                self.open_disputes.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "bank_locked_clients",
+                "Clients locked by a chargeback or a fraud rule",
+                self.locked_clients.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "bank_held_funds",
+                "Funds held by open disputes and authorizations, over all clients",
+                f64::from_bits(self.held_funds.load(Ordering::Relaxed)),
+            ),
+        ] {
+            Self::header(&mut output, name, "gauge", help);
+            let _ = writeln!(output, "{name} {value}");
+        }
+        output
+    }
+
+    fn header(output: &mut String, name: &str, r#type: &str, help: &str) {
+        let _ = writeln!(output, "# HELP {name} {help}\n# TYPE {name} {type}");
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Answers every HTTP request on the listener with the metrics, `/metrics` or not, until the
+/// runtime shuts down
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                debug!("metrics request from {peer}");
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = respond(stream, &metrics).await {
+                        debug!("metrics request failed: {err}");
+                    }
+                });
+            }
+            Err(err) => error!("failed to accept metrics connection: {err}"),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    // the request itself is not needed, only its end
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") && request.len() < 8192 {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let body = metrics.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input_file_reader::InputFileRecord,
+        service::{Service, TransactionRecordHandler},
+    };
+
+    fn record(r#type: InputFileRecordType, tx: u64, amount: Option<f64>) -> InputFileRecord {
+        InputFileRecord {
+            r#type,
+            client: 1,
+            tx,
+            amount,
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_records_and_refreshes_gauges() {
+        let mut service = Service::new();
+        service.set_metrics(Some(Arc::default()));
+        for record in [
+            record(InputFileRecordType::Deposit, 1, Some(10.0)),
+            record(InputFileRecordType::Deposit, 2, Some(1e12)),
+            record(InputFileRecordType::Dispute, 1, None),
+            record(InputFileRecordType::Dispute, 3, None),
+            // not enough funds, stored as failed without an error
+            record(InputFileRecordType::Withdrawal, 4, Some(5.0)),
+        ] {
+            let _ = service.handle(&record).await;
+        }
+        service.finish_run();
+        let output = service.metrics().unwrap().render();
+        for line in [
+            "bank_records_total{type=\"deposit\",outcome=\"accepted\"} 1",
+            "bank_records_total{type=\"deposit\",outcome=\"rejected\"} 1",
+            "bank_records_total{type=\"dispute\",outcome=\"accepted\"} 1",
+            "bank_records_total{type=\"withdrawal\",outcome=\"accepted\"} 0",
+            "bank_records_total{type=\"withdrawal\",outcome=\"rejected\"} 1",
+            "bank_rejections_total{reason=\"cannot deposit amount out of range\"} 1",
+            "bank_rejections_total{reason=\"dispute failure, transaction not found\"} 1",
+            "bank_rejections_total{reason=\"transaction stored as failed\"} 1",
+            "bank_record_duration_seconds_bucket{le=\"+Inf\"} 5",
+            "bank_record_duration_seconds_count 5",
+            "bank_open_disputes 1",
+            "bank_locked_clients 0",
+            "bank_held_funds 10",
+        ] {
+            assert!(
+                output.lines().any(|l| l == line),
+                "{line} missing in:\n{output}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Arc::new(Metrics::default());
+        metrics.set_state(2, 0, 0.0);
+        tokio::spawn(serve(listener, metrics));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nbank_open_disputes 2\n"));
+    }
+}
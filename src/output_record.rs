@@ -1,7 +1,23 @@
+use crate::{
+    fraud_rules::RuleAction,
+    input_file_reader::{InputFileRecord, InputFileRecordType},
+    pipeline::ResultStatus,
+    reorder_buffer::LatePolicy,
+    revenue_account::FeePostingStatus,
+    scheduler::AttemptOutcome,
+    transaction_info::{TransactionStatus, TransactionType},
+};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize, Serializer};
 
 pub trait OutputRecordProvider {
     fn get_records(&self) -> impl Iterator<Item = OutputRecord>;
+    fn get_statement_records(&self) -> impl Iterator<Item = StatementRecord>;
+    fn get_fee_records(&self) -> impl Iterator<Item = FeeRecord>;
+    fn get_interest_records(&self) -> impl Iterator<Item = InterestRecord>;
+    fn get_flagged_records(&self) -> impl Iterator<Item = FlaggedRecord>;
+    fn get_history_records(&self) -> impl Iterator<Item = HistoryRecord>;
+    fn get_scheduled_records(&self) -> impl Iterator<Item = ScheduledRecord>;
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -16,6 +32,135 @@ pub struct OutputRecord {
     pub locked: bool,
 }
 
+#[derive(Debug, Serialize)]
+pub struct StatementRecord {
+    pub client: u64,
+    #[serde(serialize_with = "fixed_width")]
+    pub available: f64,
+    #[serde(serialize_with = "fixed_width")]
+    pub held: f64,
+    /// Part of `held` held by open disputes
+    #[serde(serialize_with = "fixed_width")]
+    pub held_disputed: f64,
+    /// Part of `held` reserved by open authorizations
+    #[serde(serialize_with = "fixed_width")]
+    pub held_authorized: f64,
+    #[serde(serialize_with = "fixed_width")]
+    pub total: f64,
+    #[serde(serialize_with = "fixed_width")]
+    pub credit_limit: f64,
+    #[serde(serialize_with = "fixed_width")]
+    pub overdraft_used: f64,
+    pub locked: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeeRecord {
+    pub id: u64,
+    pub origin: u64,
+    pub charged_for: TransactionType,
+    pub client: u64,
+    #[serde(serialize_with = "fixed_width")]
+    pub amount: f64,
+    pub status: FeePostingStatus,
+}
+
+/// One day of accrued interest, `amount` is kept unrounded for audit
+#[derive(Debug, Serialize)]
+pub struct InterestRecord {
+    pub id: u64,
+    pub client: u64,
+    pub date: NaiveDate,
+    pub balance: f64,
+    pub annual_rate: f64,
+    pub day_count_basis: u16,
+    pub amount: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FlaggedRecord {
+    pub r#type: InputFileRecordType,
+    pub client: u64,
+    pub tx: u64,
+    pub rule: String,
+    pub action: RuleAction,
+    pub reason: String,
+}
+
+/// Deposit or withdrawal as last seen, with the operator reason if it was reversed
+#[derive(Debug, Serialize)]
+pub struct HistoryRecord {
+    pub tx: u64,
+    pub r#type: TransactionType,
+    pub client: u64,
+    pub amount: Option<f64>,
+    pub status: TransactionStatus,
+    /// Only kept for deposits that can still be disputed
+    pub timestamp: Option<DateTime<Utc>>,
+    pub reversed_at: Option<DateTime<Utc>>,
+    pub reason: Option<String>,
+}
+
+/// One attempt to materialize a scheduled instruction
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledRecord {
+    pub instruction: u64,
+    pub tx: u64,
+    pub r#type: InputFileRecordType,
+    pub client: u64,
+    #[serde(serialize_with = "fixed_width")]
+    pub amount: f64,
+    pub date: NaiveDate,
+    pub attempt: u32,
+    pub outcome: AttemptOutcome,
+}
+
+/// Record that made a batch fail, `tx` is missing when the record could not be parsed
+#[derive(Debug, Serialize)]
+pub struct BatchErrorRecord {
+    pub line: u64,
+    pub tx: Option<u64>,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LateRecord {
+    pub r#type: InputFileRecordType,
+    pub client: u64,
+    pub tx: u64,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub released_up_to: DateTime<Utc>,
+    pub policy: LatePolicy,
+}
+
+/// Outcome of one transaction record, written as the record is applied and again when
+/// reprocessing a late record changes it
+#[derive(Debug, Serialize)]
+pub struct ResultRecord {
+    pub r#type: InputFileRecordType,
+    pub client: u64,
+    pub tx: u64,
+    pub status: ResultStatus,
+    pub error: Option<String>,
+}
+
+impl ResultRecord {
+    /// `rejection` is the error message of a rejected record, see `Service::rejection`
+    pub fn new(record: &InputFileRecord, rejection: Option<&str>) -> ResultRecord {
+        let status = match rejection {
+            None => ResultStatus::Accepted,
+            Some(_) => ResultStatus::Rejected,
+        };
+        ResultRecord {
+            r#type: record.r#type,
+            client: record.client,
+            tx: record.tx,
+            status,
+            error: rejection.map(str::to_string),
+        }
+    }
+}
+
 fn fixed_width<S>(value: &f64, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -25,5 +170,6 @@ where
 
 fn trim_trailing_zeros(value: f64) -> f64 {
     // improvement: replace magic number with constant
-    (value * 10_000.0).round() / 10_000.0
+    // adding zero turns a rounded negative zero into zero so small overdrafts don't print as "-0"
+    (value * 10_000.0).round() / 10_000.0 + 0.0
 }
@@ -1,6 +1,7 @@
-use crate::output_record::OutputRecord;
+use serde::Serialize;
 use std::io::Write;
 
+#[derive(Default)]
 pub struct OutputWriter {}
 
 impl OutputWriter {
@@ -8,10 +9,10 @@ impl OutputWriter {
         Self {}
     }
 
-    pub fn write<W: Write>(
+    pub fn write<W: Write, R: Serialize>(
         &self,
         writer: W,
-        records: impl Iterator<Item = OutputRecord>,
+        records: impl Iterator<Item = R>,
     ) -> anyhow::Result<()> {
         let mut wtr = csv::Writer::from_writer(writer);
         for record in records {
@@ -0,0 +1,336 @@
+//! Reading, applying and result writing as stages running concurrently. The stages are connected
+//! by bounded channels, so a slow stage holds the others back instead of records piling up
+use crate::{
+    input_file_reader::InputFileRecord,
+    output_record::ResultRecord,
+    output_writer::OutputWriter,
+    reorder_buffer::EventTimeProcessor,
+    service::{Service, TransactionRecordHandler},
+};
+use anyhow::{bail, Context};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::{io::Write, iter, mem};
+use tokio::{
+    sync::mpsc::{self, Receiver, Sender},
+    task::{self, JoinHandle},
+};
+use tracing::error;
+
+/// Records and results move between stages in batches, a wakeup per record costs more than
+/// applying it
+const MAX_BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Records a channel holds before the stage feeding it waits
+    pub channel_capacity: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            channel_capacity: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Display, Serialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultStatus {
+    Accepted,
+    Rejected,
+}
+
+/// The reader stage parses the file on a blocking thread, the engine stage applies the records
+/// on the calling task and the optional sink stage writes their results on another blocking thread
+pub struct Pipeline {
+    capacity: usize,
+    results: Option<Box<dyn Write + Send>>,
+}
+
+impl Pipeline {
+    pub fn new(config: &PipelineConfig) -> anyhow::Result<Pipeline> {
+        if config.channel_capacity == 0 {
+            bail!("pipeline channel capacity must be at least 1");
+        }
+        Ok(Self {
+            capacity: config.channel_capacity,
+            results: None,
+        })
+    }
+
+    /// Streams the result of every record to the writer as the record is applied
+    pub fn with_results(self, writer: impl Write + Send + 'static) -> Pipeline {
+        Self {
+            results: Some(Box::new(writer)),
+            ..self
+        }
+    }
+
+    /// Applies the records in file order
+    pub async fn run(
+        self,
+        service: &mut Service,
+        records: impl Iterator<Item = InputFileRecord> + Send + 'static,
+    ) -> anyhow::Result<()> {
+        let mut stages = self.start(records);
+        while let Some(batch) = stages.records.recv().await {
+            for record in batch {
+                let outcome = service.handle(&record).await;
+                if let Err(err) = &outcome {
+                    record
+                        .span()
+                        .in_scope(|| error!("transaction failure: {err}"));
+                }
+                let rejection = service.rejection(&record, &outcome);
+                stages
+                    .send(ResultRecord::new(&record, rejection.as_deref()))
+                    .await;
+            }
+        }
+        stages.finish().await
+    }
+
+    /// Applies the records in event time order, results follow the order records are applied in
+    pub async fn run_in_event_time(
+        self,
+        service: &mut Service,
+        records: impl Iterator<Item = InputFileRecord> + Send + 'static,
+        processor: &mut EventTimeProcessor,
+    ) -> anyhow::Result<()> {
+        let mut stages = self.start(records);
+        while let Some(batch) = stages.records.recv().await {
+            for record in batch {
+                for result in processor.push(service, record).await {
+                    stages.send(result).await;
+                }
+            }
+        }
+        for result in processor.finish(service).await {
+            stages.send(result).await;
+        }
+        stages.finish().await
+    }
+
+    /// A channel holds up to `capacity` records, in batches
+    fn start(self, records: impl Iterator<Item = InputFileRecord> + Send + 'static) -> Stages {
+        let batch_size = self.capacity.min(MAX_BATCH_SIZE);
+        let batches = self.capacity / batch_size;
+        let (record_sender, record_receiver) = mpsc::channel(batches);
+        let mut records = records.peekable();
+        let reader = task::spawn_blocking(move || {
+            while records.peek().is_some() {
+                let batch = records.by_ref().take(batch_size).collect();
+                // the engine stage is gone, there is no one left to read for
+                if record_sender.blocking_send(batch).is_err() {
+                    break;
+                }
+            }
+        });
+        let (results, sink) = match self.results {
+            Some(writer) => {
+                let (result_sender, mut result_receiver) = mpsc::channel(batches);
+                let sink = task::spawn_blocking(move || {
+                    let results = iter::from_fn(|| result_receiver.blocking_recv()).flatten();
+                    OutputWriter::new().write(writer, results)
+                });
+                (Some(result_sender), Some(sink))
+            }
+            None => (None, None),
+        };
+        Stages {
+            records: record_receiver,
+            reader,
+            batch_size,
+            pending: Vec::new(),
+            results,
+            sink,
+        }
+    }
+}
+
+struct Stages {
+    records: Receiver<Vec<InputFileRecord>>,
+    reader: JoinHandle<()>,
+    batch_size: usize,
+    /// Results of the batch being applied
+    pending: Vec<ResultRecord>,
+    results: Option<Sender<Vec<ResultRecord>>>,
+    sink: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Stages {
+    async fn send(&mut self, result: ResultRecord) {
+        if self.results.is_none() {
+            return;
+        }
+        self.pending.push(result);
+        if self.pending.len() == self.batch_size {
+            self.flush().await;
+        }
+    }
+
+    async fn flush(&mut self) {
+        if let Some(results) = &self.results {
+            // the sink stopped on a write error, which is reported when it is joined
+            if results.send(mem::take(&mut self.pending)).await.is_err() {
+                self.results = None;
+            }
+        }
+    }
+
+    /// Waits for the other stages once the engine stage has applied the last record, each stage
+    /// drains what it holds first
+    async fn finish(mut self) -> anyhow::Result<()> {
+        if !self.pending.is_empty() {
+            self.flush().await;
+        }
+        // closing the channel lets the sink write the results it still holds and stop
+        drop(self.results);
+        self.reader.await.context("reader stage failed")?;
+        if let Some(sink) = self.sink {
+            sink.await
+                .context("results stage failed")?
+                .context("failed to write results")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input_file_reader::{InputFileReader, InputFileRecordType},
+        output_record::OutputRecordProvider,
+        reorder_buffer::{LatePolicy, ReorderConfig},
+        shared_buffer::SharedBuffer,
+    };
+
+    /// Result rows without the header
+    fn rows(buffer: &SharedBuffer) -> Vec<String> {
+        buffer
+            .contents()
+            .lines()
+            .skip(1)
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn records(path: &str) -> impl Iterator<Item = InputFileRecord> + Send + 'static {
+        InputFileReader::new(path.to_string())
+            .read_file()
+            .expect("file should be readable")
+    }
+
+    #[tokio::test]
+    async fn results_follow_file_order() {
+        let mut expected = Service::new();
+        let mut statuses = Vec::new();
+        for record in records("tests/assets/transactions.csv") {
+            let outcome = expected.handle(&record).await;
+            statuses.push(expected.rejection(&record, &outcome).is_none());
+        }
+
+        let buffer = SharedBuffer::default();
+        let config = PipelineConfig {
+            channel_capacity: 1,
+        };
+        let mut service = Service::new();
+        Pipeline::new(&config)
+            .unwrap()
+            .with_results(buffer.clone())
+            .run(&mut service, records("tests/assets/transactions.csv"))
+            .await
+            .expect("pipeline should finish");
+        let rows = rows(&buffer);
+        assert_eq!(rows.len(), 9);
+        assert_eq!(rows[0], "deposit,1,1,accepted,");
+        assert_eq!(
+            rows.iter()
+                .map(|row| row.contains(",accepted,"))
+                .collect::<Vec<_>>(),
+            statuses
+        );
+        assert_eq!(
+            service
+                .get_records()
+                .map(|record| record.available)
+                .collect::<Vec<_>>(),
+            expected
+                .get_records()
+                .map(|record| record.available)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn results_follow_event_time_order() {
+        let buffer = SharedBuffer::default();
+        let config = PipelineConfig {
+            channel_capacity: 1,
+        };
+        let mut processor = EventTimeProcessor::new(&ReorderConfig {
+            max_lateness_secs: 60,
+            late_policy: LatePolicy::Reject,
+            max_reprocess_history: 10,
+            checkpoint_interval: 10,
+        });
+        let mut service = Service::new();
+        Pipeline::new(&config)
+            .unwrap()
+            .with_results(buffer.clone())
+            .run_in_event_time(
+                &mut service,
+                records("tests/assets/transactions_timestamped.csv"),
+                &mut processor,
+            )
+            .await
+            .expect("pipeline should finish");
+        // the dispute of tx 2 happened first, tx 1 was partly withdrawn by then
+        let rows = rows(&buffer);
+        assert_eq!(rows[3], "dispute,1,2,accepted,");
+        assert!(rows[4].starts_with("dispute,1,1,rejected,"));
+        assert_eq!(rows.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn records_stored_as_failed_are_rejected() {
+        let buffer = SharedBuffer::default();
+        let record = |r#type, tx, amount| InputFileRecord {
+            r#type,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            timestamp: None,
+            reason: None,
+        };
+        let records = vec![
+            record(InputFileRecordType::Deposit, 1, 5.0),
+            record(InputFileRecordType::Withdrawal, 2, 50.0),
+        ];
+        Pipeline::new(&PipelineConfig::default())
+            .unwrap()
+            .with_results(buffer.clone())
+            .run(&mut Service::new(), records.into_iter())
+            .await
+            .expect("pipeline should finish");
+        assert_eq!(
+            rows(&buffer),
+            vec![
+                "deposit,1,1,accepted,",
+                "withdrawal,1,2,rejected,transaction stored as failed: 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_empty_channels() {
+        let config = PipelineConfig {
+            channel_capacity: 0,
+        };
+        assert!(Pipeline::new(&config).is_err());
+    }
+}
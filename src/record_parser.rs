@@ -0,0 +1,328 @@
+//! Hand-rolled parsing of input rows from byte records, without serde or allocations
+use crate::input_file_reader::{InputFileRecord, InputFileRecordType};
+use chrono::{DateTime, FixedOffset, Utc};
+use csv::{ByteRecord, StringRecord};
+
+/// Powers of ten that are exact in an `f64`
+const POWERS_OF_TEN: [f64; 23] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16,
+    1e17, 1e18, 1e19, 1e20, 1e21, 1e22,
+];
+
+/// Largest mantissa that is exact in an `f64`
+const MAX_EXACT_MANTISSA: u64 = 1 << 53;
+
+/// Column positions of the record fields, from the header row.
+///
+/// Parses the rows it fully understands, which are expected to be nearly all of them, and gives
+/// up on the others so that the serde path decides, with the same result and error
+#[derive(Debug)]
+pub struct RecordParser {
+    columns: usize,
+    r#type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    timestamp: Option<usize>,
+    reason: Option<usize>,
+}
+
+impl RecordParser {
+    /// None if a required column is missing or a column is named twice
+    pub fn new(headers: &StringRecord) -> Option<RecordParser> {
+        let column = |name: &str| -> Result<Option<usize>, ()> {
+            let mut positions = headers.iter().enumerate().filter(|(_, h)| *h == name);
+            match (positions.next(), positions.next()) {
+                (_, Some(_)) => Err(()),
+                (position, None) => Ok(position.map(|(index, _)| index)),
+            }
+        };
+        Some(RecordParser {
+            columns: headers.len(),
+            r#type: column("type").ok()??,
+            client: column("client").ok()??,
+            tx: column("tx").ok()??,
+            amount: column("amount").ok()?,
+            timestamp: column("timestamp").ok()?,
+            reason: column("reason").ok()?,
+        })
+    }
+
+    /// The untrimmed record, None if the serde path has to decide
+    pub fn parse(&self, record: &ByteRecord) -> Option<InputFileRecord> {
+        // non-ASCII text may be invalid or trimmed as Unicode whitespace by the serde path
+        if record.len() != self.columns || !record.as_slice().is_ascii() {
+            return None;
+        }
+        let field = |index: usize| {
+            let field = record[index].trim_ascii();
+            // a vertical tab is Unicode whitespace but not ASCII whitespace
+            match (field.first(), field.last()) {
+                (Some(b'\x0b'), _) | (_, Some(b'\x0b')) => None,
+                _ => Some(field),
+            }
+        };
+        let optional = |index: Option<usize>| match index {
+            Some(index) => field(index),
+            None => Some(&b""[..]),
+        };
+        let amount = optional(self.amount)?;
+        let timestamp = optional(self.timestamp)?;
+        let reason = optional(self.reason)?;
+        Some(InputFileRecord {
+            r#type: parse_type(field(self.r#type)?)?,
+            client: parse_u64(field(self.client)?)?,
+            tx: parse_u64(field(self.tx)?)?,
+            amount: match amount {
+                b"" => None,
+                amount => Some(parse_f64(amount)?),
+            },
+            timestamp: match timestamp {
+                b"" => None,
+                timestamp => Some(
+                    std::str::from_utf8(timestamp)
+                        .ok()?
+                        .parse::<DateTime<FixedOffset>>()
+                        .ok()?
+                        .with_timezone(&Utc),
+                ),
+            },
+            reason: match reason {
+                b"" => None,
+                reason => Some(String::from_utf8(reason.to_vec()).ok()?),
+            },
+        })
+    }
+}
+
+fn parse_type(field: &[u8]) -> Option<InputFileRecordType> {
+    Some(match field {
+        b"deposit" => InputFileRecordType::Deposit,
+        b"withdrawal" => InputFileRecordType::Withdrawal,
+        b"dispute" => InputFileRecordType::Dispute,
+        b"resolve" => InputFileRecordType::Resolve,
+        b"chargeback" => InputFileRecordType::Chargeback,
+        b"reversal" => InputFileRecordType::Reversal,
+        b"authorize" => InputFileRecordType::Authorize,
+        b"capture" => InputFileRecordType::Capture,
+        b"void" => InputFileRecordType::Void,
+        _ => return None,
+    })
+}
+
+/// Plain decimal digits only, signs and hexadecimal are left to the serde path
+fn parse_u64(field: &[u8]) -> Option<u64> {
+    if field.is_empty() {
+        return None;
+    }
+    field.iter().try_fold(0u64, |value, byte| {
+        let digit = byte.checked_sub(b'0').filter(|digit| *digit < 10)?;
+        value.checked_mul(10)?.checked_add(u64::from(digit))
+    })
+}
+
+/// Fixed-point amounts such as `-12.3456`, other notations go through `str::parse`.
+///
+/// An exact mantissa divided by an exact power of ten rounds once, so the result is the correctly
+/// rounded value `str::parse` returns as well
+fn parse_f64(field: &[u8]) -> Option<f64> {
+    let (negative, digits) = match field.split_first() {
+        Some((b'-', digits)) => (true, digits),
+        _ => (false, field),
+    };
+    let (integer, fraction) = match digits.iter().position(|byte| *byte == b'.') {
+        Some(dot) => (&digits[..dot], &digits[dot + 1..]),
+        None => (digits, &b""[..]),
+    };
+    let mantissa =
+        if (integer.is_empty() && fraction.is_empty()) || fraction.len() >= POWERS_OF_TEN.len() {
+            None
+        } else {
+            integer
+                .iter()
+                .chain(fraction)
+                .try_fold(0u64, |mantissa, byte| {
+                    let digit = byte.checked_sub(b'0').filter(|digit| *digit < 10)?;
+                    mantissa
+                        .checked_mul(10)?
+                        .checked_add(u64::from(digit))
+                        .filter(|mantissa| *mantissa <= MAX_EXACT_MANTISSA)
+                })
+        };
+    match mantissa {
+        Some(mantissa) => {
+            let value = mantissa as f64 / POWERS_OF_TEN[fraction.len()];
+            Some(if negative { -value } else { value })
+        }
+        None => std::str::from_utf8(field).ok()?.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_file_reader::InputFileReader;
+    use csv::{ReaderBuilder, Trim};
+    use proptest::prelude::*;
+
+    /// Line numbers with the record or the error, comparable across both paths
+    type Parsed = Vec<(u64, Result<String, String>)>;
+
+    /// The reader before the hand-rolled parser, string records deserialized with serde
+    fn serde_records(input: &[u8]) -> Result<Parsed, String> {
+        let mut rdr = ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(input);
+        let headers = rdr.headers().map_err(|err| err.to_string())?.clone();
+        Ok(rdr
+            .records()
+            .enumerate()
+            .map(|(index, record)| {
+                let line = record
+                    .as_ref()
+                    .ok()
+                    .and_then(|record| record.position())
+                    .map_or(index as u64 + 2, |position| position.line());
+                let record = record
+                    .and_then(|record| record.deserialize::<InputFileRecord>(Some(&headers)))
+                    .map(|record| format!("{record:?}"))
+                    .map_err(|err| err.to_string());
+                (line, record)
+            })
+            .collect())
+    }
+
+    fn parsed_records(input: &[u8]) -> Result<Parsed, String> {
+        Ok(InputFileReader::parse_strict(input)
+            .map_err(|err| err.to_string())?
+            .into_iter()
+            .map(|(line, record)| {
+                let record = record
+                    .map(|record| format!("{record:?}"))
+                    .map_err(|err| err.to_string());
+                (line, record)
+            })
+            .collect())
+    }
+
+    fn field_strategy() -> impl Strategy<Value = Vec<u8>> {
+        let known = prop::sample::select(vec![
+            "",
+            "deposit",
+            "withdrawal",
+            "dispute",
+            "resolve",
+            "chargeback",
+            "reversal",
+            "authorize",
+            "capture",
+            "void",
+            "Deposit",
+            " deposit ",
+            "refund",
+            "0",
+            "1",
+            "42",
+            "007",
+            "+7",
+            "-3",
+            "0x1f",
+            "18446744073709551615",
+            "18446744073709551616",
+            " 12 ",
+            "\u{a0}5",
+            "\x0b5",
+            "5\x0b",
+            "1.5",
+            "-2.25",
+            ".5",
+            "5.",
+            "-.5",
+            "-0",
+            "-",
+            ".",
+            "1.2.3",
+            "1e3",
+            "NaN",
+            "inf",
+            "-inf",
+            "123456789.123456789",
+            "9007199254740993",
+            "0.00000000000000000000001",
+            "１",
+            "2024-01-01T09:00:00Z",
+            "2024-01-01T09:00:00+02:00",
+            "2024-01-01 09:00:00Z",
+            "yesterday",
+            "fraud",
+            "\u{a0}fraud ",
+            "\"quoted, field\"",
+        ])
+        .prop_map(|field| field.as_bytes().to_vec());
+        let fixed_point = (
+            any::<bool>(),
+            0..100_000_000_000u64,
+            0..=u64::MAX,
+            0..24usize,
+        )
+            .prop_map(|(negative, integer, fraction, digits)| {
+                let fraction = format!("{fraction:020}");
+                let sign = if negative { "-" } else { "" };
+                format!("{sign}{integer}.{}", &fraction[..digits.min(20)]).into_bytes()
+            });
+        let field = prop_oneof![
+            6 => known,
+            3 => fixed_point,
+            1 => any::<f64>().prop_map(|value| value.to_string().into_bytes()),
+            1 => prop::collection::vec(any::<u8>(), 0..6),
+        ];
+        // ASCII whitespace, whitespace that is only Unicode whitespace, and mixes of both
+        let padding = prop::sample::select(vec!["", "", "", " ", "\t", "\x0b", "\u{a0}", " \x0b "]);
+        (padding.clone(), field, padding).prop_map(|(before, field, after)| {
+            [before.as_bytes(), &field, after.as_bytes()].concat()
+        })
+    }
+
+    fn input_strategy() -> impl Strategy<Value = Vec<u8>> {
+        let columns = vec![
+            "type",
+            "client",
+            "tx",
+            "amount",
+            "timestamp",
+            "reason",
+            "memo",
+            " amount ",
+            "tx",
+        ];
+        let headers = prop::sample::subsequence(columns, 0..=9).prop_shuffle();
+        let rows = prop::collection::vec(prop::collection::vec(field_strategy(), 0..9), 0..12);
+        (headers, rows).prop_map(|(headers, rows)| {
+            let mut input = headers.join(",").into_bytes();
+            for row in rows {
+                input.push(b'\n');
+                input.extend(row.join(&b","[..]));
+            }
+            input
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(2_000))]
+
+        #[test]
+        fn matches_serde_path(input in input_strategy()) {
+            prop_assert_eq!(parsed_records(&input), serde_records(&input));
+        }
+    }
+
+    #[test]
+    fn fixed_point_amounts() {
+        assert_eq!(parse_f64(b"0.1"), Some(0.1));
+        assert_eq!(parse_f64(b"-12.3456"), Some(-12.3456));
+        assert_eq!(parse_f64(b"9007199254740993"), Some(9007199254740992.0));
+        assert_eq!(parse_f64(b"1e3"), Some(1000.0));
+        assert_eq!(parse_f64(b"1,5"), None);
+    }
+}
@@ -0,0 +1,740 @@
+use crate::{
+    audit_log::AuditEvent,
+    input_file_reader::InputFileRecord,
+    output_record::{LateRecord, ResultRecord},
+    service::{Checkpoint, Service, TransactionRecordHandler},
+};
+use chrono::{DateTime, Duration, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    time::Instant,
+};
+use tracing::{debug, error, warn};
+
+/// What happens to a record whose event time is before records already applied
+#[derive(Debug, Display, Deserialize, Serialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LatePolicy {
+    /// Record is not applied
+    Reject,
+    /// Record is applied out of order
+    ApplyLate,
+    /// State goes back to the last checkpoint before the late record and the records applied
+    /// since are replayed together with the late one, rejected when it is older than every
+    /// record kept in the reprocess history
+    Reprocess,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReorderConfig {
+    pub max_lateness_secs: u32,
+    pub late_policy: LatePolicy,
+    /// Last applied records kept for reprocessing, late records older than all of them are
+    /// rejected
+    #[serde(default = "default_max_reprocess_history")]
+    pub max_reprocess_history: usize,
+    /// Applied records between checkpoints of the state, a late record replays the records
+    /// after the checkpoint before it. Every checkpoint holds a copy of the whole state, at most
+    /// `max_reprocess_history / checkpoint_interval + 2` are kept
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: usize,
+}
+
+fn default_max_reprocess_history() -> usize {
+    100_000
+}
+
+fn default_checkpoint_interval() -> usize {
+    10_000
+}
+
+struct Pending {
+    event_time: DateTime<Utc>,
+    sequence: u64,
+    record: InputFileRecord,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.event_time, self.sequence).cmp(&(other.event_time, other.sequence))
+    }
+}
+
+#[derive(Debug)]
+pub enum Released {
+    InOrder(InputFileRecord),
+    /// Event time is before `released_up_to`, the last event time already released
+    Late {
+        record: InputFileRecord,
+        released_up_to: DateTime<Utc>,
+    },
+}
+
+/// Holds records back until no record within the lateness bound can precede them.
+/// Records without timestamp are ordered after every record seen so far
+pub struct ReorderBuffer {
+    max_lateness: Duration,
+    pending: BinaryHeap<Reverse<Pending>>,
+    max_event_time: Option<DateTime<Utc>>,
+    released_up_to: Option<DateTime<Utc>>,
+    sequence: u64,
+}
+
+impl ReorderBuffer {
+    pub fn new(max_lateness: Duration) -> ReorderBuffer {
+        Self {
+            max_lateness,
+            pending: Default::default(),
+            max_event_time: None,
+            released_up_to: None,
+            sequence: 0,
+        }
+    }
+
+    pub fn push(&mut self, record: InputFileRecord) -> Vec<Released> {
+        let Some(event_time) = record.timestamp.or(self.max_event_time) else {
+            // nothing timestamped so far, file order is all there is
+            return vec![Released::InOrder(record)];
+        };
+        if let Some(released_up_to) = self.released_up_to {
+            if event_time < released_up_to {
+                return vec![Released::Late {
+                    record,
+                    released_up_to,
+                }];
+            }
+        }
+        let max_event_time = self
+            .max_event_time
+            .map_or(event_time, |max_event_time| max_event_time.max(event_time));
+        self.max_event_time = Some(max_event_time);
+        self.sequence += 1;
+        self.pending.push(Reverse(Pending {
+            event_time,
+            sequence: self.sequence,
+            record,
+        }));
+
+        let watermark = max_event_time - self.max_lateness;
+        let mut released = Vec::new();
+        while self
+            .pending
+            .peek()
+            .is_some_and(|Reverse(pending)| pending.event_time <= watermark)
+        {
+            let Reverse(pending) = self.pending.pop().expect("pending record should exist");
+            self.released_up_to = Some(pending.event_time);
+            released.push(Released::InOrder(pending.record));
+        }
+        released
+    }
+
+    /// Releases everything still held back, in event time order
+    pub fn drain(&mut self) -> Vec<InputFileRecord> {
+        let mut released = Vec::with_capacity(self.pending.len());
+        while let Some(Reverse(pending)) = self.pending.pop() {
+            self.released_up_to = Some(pending.event_time);
+            released.push(pending.record);
+        }
+        released
+    }
+}
+
+/// A record applied while keeping the reprocess history
+struct Applied {
+    event_time: DateTime<Utc>,
+    record: InputFileRecord,
+    /// Error message when the record was rejected, see `Service::rejection`
+    rejection: Option<String>,
+}
+
+/// Applies records to the service in event time order and handles late records per policy
+pub struct EventTimeProcessor {
+    buffer: ReorderBuffer,
+    late_policy: LatePolicy,
+    /// Applied records in event time order, only kept for reprocessing. The oldest ones are
+    /// dropped together with their checkpoint once `max_history` records follow the next one
+    history: Vec<Applied>,
+    max_history: usize,
+    /// State before the history record at the index, the first one before the oldest record
+    checkpoints: Vec<(usize, Checkpoint)>,
+    checkpoint_interval: usize,
+    /// Records applied again while reprocessing, late records included
+    replayed_records: u64,
+    late_records: Vec<LateRecord>,
+}
+
+impl EventTimeProcessor {
+    pub fn new(config: &ReorderConfig) -> EventTimeProcessor {
+        Self {
+            buffer: ReorderBuffer::new(Duration::seconds(config.max_lateness_secs as i64)),
+            late_policy: config.late_policy,
+            history: Vec::new(),
+            max_history: config.max_reprocess_history,
+            checkpoints: Vec::new(),
+            checkpoint_interval: config.checkpoint_interval.max(1),
+            replayed_records: 0,
+            late_records: Vec::new(),
+        }
+    }
+
+    pub fn late_records(&self) -> impl Iterator<Item = &LateRecord> {
+        self.late_records.iter()
+    }
+
+    pub fn replayed_records(&self) -> u64 {
+        self.replayed_records
+    }
+
+    /// Results of the records released by this one, in the order they were applied. Records
+    /// whose outcome changed when reprocessed are reported again after the late record
+    pub async fn push(
+        &mut self,
+        service: &mut Service,
+        record: InputFileRecord,
+    ) -> Vec<ResultRecord> {
+        let mut results = Vec::new();
+        for released in self.buffer.push(record) {
+            match released {
+                Released::InOrder(record) => results.push(self.apply(service, record).await),
+                Released::Late {
+                    record,
+                    released_up_to,
+                } => results.extend(self.handle_late(service, record, released_up_to).await),
+            }
+        }
+        results
+    }
+
+    pub async fn finish(&mut self, service: &mut Service) -> Vec<ResultRecord> {
+        let mut results = Vec::new();
+        for record in self.buffer.drain() {
+            results.push(self.apply(service, record).await);
+        }
+        results
+    }
+
+    /// Whether a checkpoint is due before the history record at `index`
+    fn checkpoint_due(&self, index: usize) -> bool {
+        self.checkpoints
+            .last()
+            .is_none_or(|(checkpointed, _)| index - checkpointed >= self.checkpoint_interval)
+    }
+
+    /// Drops the oldest records and their checkpoint while the next checkpoint still has
+    /// `max_history` records after it
+    fn slide_history(&mut self) {
+        while self.checkpoints.len() > 1
+            && self.history.len() - self.checkpoints[1].0 >= self.max_history
+        {
+            let dropped = self.checkpoints[1].0;
+            self.checkpoints.remove(0);
+            self.history.drain(..dropped);
+            for (index, _) in &mut self.checkpoints {
+                *index -= dropped;
+            }
+        }
+    }
+
+    async fn apply(&mut self, service: &mut Service, record: InputFileRecord) -> ResultRecord {
+        let keeps_history = self.late_policy == LatePolicy::Reprocess;
+        if keeps_history && self.checkpoint_due(self.history.len()) {
+            self.checkpoints
+                .push((self.history.len(), service.checkpoint()));
+        }
+        let outcome = service.handle(&record).await;
+        if let Err(err) = &outcome {
+            record
+                .span()
+                .in_scope(|| error!("transaction failure: {err}"));
+        }
+        let rejection = service.rejection(&record, &outcome);
+        let result = ResultRecord::new(&record, rejection.as_deref());
+        if keeps_history {
+            let event_time = record
+                .timestamp
+                .or(self.history.last().map(|applied| applied.event_time))
+                .unwrap_or(DateTime::<Utc>::MIN_UTC);
+            self.history.push(Applied {
+                event_time,
+                record,
+                rejection,
+            });
+            self.slide_history();
+        }
+        result
+    }
+
+    async fn handle_late(
+        &mut self,
+        service: &mut Service,
+        record: InputFileRecord,
+        released_up_to: DateTime<Utc>,
+    ) -> Vec<ResultRecord> {
+        warn!(
+            "late transaction {} from {:?}, already applied up to {released_up_to}, policy: {}",
+            record.tx, record.timestamp, self.late_policy
+        );
+        self.late_records.push(LateRecord {
+            r#type: record.r#type,
+            client: record.client,
+            tx: record.tx,
+            timestamp: record.timestamp,
+            released_up_to,
+            policy: self.late_policy,
+        });
+        let event_time = record.timestamp.unwrap_or(released_up_to);
+        match self.late_policy {
+            LatePolicy::Reprocess
+                if self
+                    .history
+                    .first()
+                    .is_none_or(|oldest| event_time < oldest.event_time) =>
+            {
+                let rejection = format!(
+                    "late record: already applied up to {released_up_to} and older than the last {} records kept to reprocess",
+                    self.max_history
+                );
+                service.reject(&record, &rejection);
+                vec![ResultRecord::new(&record, Some(&rejection))]
+            }
+            LatePolicy::Reject => {
+                let rejection = format!("late record: already applied up to {released_up_to}");
+                service.reject(&record, &rejection);
+                vec![ResultRecord::new(&record, Some(&rejection))]
+            }
+            LatePolicy::ApplyLate => {
+                let outcome = service.handle(&record).await;
+                if let Err(err) = &outcome {
+                    record
+                        .span()
+                        .in_scope(|| error!("transaction failure: {err}"));
+                }
+                let rejection = service.rejection(&record, &outcome);
+                vec![ResultRecord::new(&record, rejection.as_deref())]
+            }
+            LatePolicy::Reprocess => {
+                let position = self
+                    .history
+                    .partition_point(|applied| applied.event_time <= event_time);
+                self.history.insert(
+                    position,
+                    Applied {
+                        event_time,
+                        record,
+                        rejection: None,
+                    },
+                );
+                // later checkpoints miss the late record
+                let valid = self
+                    .checkpoints
+                    .partition_point(|(index, _)| *index <= position);
+                self.checkpoints.truncate(valid);
+                let (start, checkpoint) = self
+                    .checkpoints
+                    .last()
+                    .expect("oldest history record should be checkpointed");
+                let start = *start;
+                service.restore(checkpoint.clone());
+                // replays are not counted or audited again, only the late record and the records
+                // whose outcome changed are
+                let metrics = service.metrics().cloned();
+                let audit_log = service.audit_log().cloned();
+                service.set_metrics(None);
+                service.set_audit_log(None);
+                let mut results = Vec::new();
+                let mut audit_events = Vec::new();
+                for index in start..self.history.len() {
+                    if index > start && self.checkpoint_due(index) {
+                        self.checkpoints.push((index, service.checkpoint()));
+                    }
+                    let record = &self.history[index].record;
+                    let started = Instant::now();
+                    let outcome = service.handle(record).await;
+                    self.replayed_records += 1;
+                    if let Err(err) = &outcome {
+                        record
+                            .span()
+                            .in_scope(|| debug!("transaction failure during reprocessing: {err}"));
+                    }
+                    let rejection = service.rejection(record, &outcome);
+                    if index == position {
+                        if let Some(metrics) = &metrics {
+                            metrics.record(record.r#type, rejection.as_deref(), started.elapsed());
+                        }
+                        results.push(ResultRecord::new(record, rejection.as_deref()));
+                        audit_events.push(AuditEvent::record(record, rejection.as_deref()));
+                    } else if rejection != self.history[index].rejection {
+                        // the late record changed what this one found, report it again
+                        debug!(
+                            "outcome of transaction {} changed by reprocessing: {rejection:?}",
+                            record.tx
+                        );
+                        results.push(ResultRecord::new(record, rejection.as_deref()));
+                        audit_events.push(AuditEvent::record(record, rejection.as_deref()));
+                    }
+                    self.history[index].rejection = rejection;
+                }
+                service.set_metrics(metrics);
+                service.set_audit_log(audit_log);
+                service.audit(|| AuditEvent::Reprocessed {
+                    tx: self.history[position].record.tx,
+                    records: self.history.len() - start,
+                });
+                for event in audit_events {
+                    service.audit(|| event);
+                }
+                self.slide_history();
+                results
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit_log::AuditLog, input_file_reader::InputFileRecordType, metrics::Metrics,
+        output_record::OutputRecordProvider, shared_buffer::SharedBuffer,
+    };
+    use std::sync::{Arc, Mutex};
+
+    fn record(tx: u64, timestamp: Option<&str>) -> InputFileRecord {
+        InputFileRecord {
+            r#type: InputFileRecordType::Deposit,
+            client: 1,
+            tx,
+            amount: Some(1.0),
+            timestamp: timestamp.map(|time| time.parse().unwrap()),
+            reason: None,
+        }
+    }
+
+    fn released_ids(released: Vec<Released>) -> Vec<(u64, bool)> {
+        released
+            .into_iter()
+            .map(|released| match released {
+                Released::InOrder(record) => (record.tx, false),
+                Released::Late { record, .. } => (record.tx, true),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reorders_within_lateness_bound() {
+        let mut buffer = ReorderBuffer::new(Duration::seconds(60));
+        assert!(buffer
+            .push(record(1, Some("2024-01-01T10:00:30Z")))
+            .is_empty());
+        assert!(buffer
+            .push(record(2, Some("2024-01-01T10:00:00Z")))
+            .is_empty());
+        assert!(buffer.push(record(3, None)).is_empty());
+        assert_eq!(
+            released_ids(buffer.push(record(4, Some("2024-01-01T10:01:31Z")))),
+            vec![(2, false), (1, false), (3, false)]
+        );
+        assert_eq!(
+            released_ids(buffer.push(record(5, Some("2024-01-01T10:00:10Z")))),
+            vec![(5, true)]
+        );
+        assert_eq!(
+            buffer
+                .drain()
+                .into_iter()
+                .map(|record| record.tx)
+                .collect::<Vec<_>>(),
+            vec![4]
+        );
+    }
+
+    async fn process(late_policy: LatePolicy) -> f64 {
+        let config = ReorderConfig {
+            max_lateness_secs: 60,
+            late_policy,
+            max_reprocess_history: 10,
+            checkpoint_interval: 2,
+        };
+        let mut processor = EventTimeProcessor::new(&config);
+        let mut service = Service::new();
+        let records = [
+            (
+                InputFileRecordType::Deposit,
+                1,
+                10.0,
+                "2024-01-01T10:00:00Z",
+            ),
+            (
+                InputFileRecordType::Withdrawal,
+                2,
+                15.0,
+                "2024-01-01T10:10:00Z",
+            ),
+            (
+                InputFileRecordType::Deposit,
+                3,
+                10.0,
+                "2024-01-01T10:20:00Z",
+            ),
+            (
+                InputFileRecordType::Deposit,
+                4,
+                10.0,
+                "2024-01-01T10:05:00Z",
+            ),
+        ];
+        for (r#type, tx, amount, timestamp) in records {
+            let record = InputFileRecord {
+                r#type,
+                amount: Some(amount),
+                ..record(tx, Some(timestamp))
+            };
+            processor.push(&mut service, record).await;
+        }
+        processor.finish(&mut service).await;
+        assert_eq!(processor.late_records().count(), 1);
+        let available = service.get_records().next().unwrap().available;
+        available
+    }
+
+    #[tokio::test]
+    async fn late_record_policies() {
+        // the withdrawal fails in event time order unless the late deposit is replayed before it
+        assert_eq!(process(LatePolicy::Reject).await, 20.0);
+        assert_eq!(process(LatePolicy::ApplyLate).await, 30.0);
+        assert_eq!(process(LatePolicy::Reprocess).await, 15.0);
+    }
+
+    #[tokio::test]
+    async fn reprocess_history_slides() {
+        let mut processor = EventTimeProcessor::new(&ReorderConfig {
+            max_lateness_secs: 60,
+            late_policy: LatePolicy::Reprocess,
+            max_reprocess_history: 3,
+            checkpoint_interval: 1,
+        });
+        let mut service = Service::new();
+        let mut results = Vec::new();
+        for (tx, timestamp) in [
+            (1, "2024-01-01T10:00:00Z"),
+            (2, "2024-01-01T10:10:00Z"),
+            (3, "2024-01-01T10:20:00Z"),
+            (4, "2024-01-01T10:30:00Z"),
+            (5, "2024-01-01T10:40:00Z"),
+            (6, "2024-01-01T10:50:00Z"),
+            (7, "2024-01-01T10:25:00Z"),
+            (8, "2024-01-01T10:05:00Z"),
+        ] {
+            results.extend(
+                processor
+                    .push(&mut service, record(tx, Some(timestamp)))
+                    .await,
+            );
+        }
+        let errors = results
+            .iter()
+            .map(|result| (result.tx, result.error.is_some()))
+            .collect::<Vec<_>>();
+        // 3, 4 and 5 are kept when 7 comes, 8 is older than all of them
+        assert_eq!(
+            errors,
+            vec![
+                (1, false),
+                (2, false),
+                (3, false),
+                (4, false),
+                (5, false),
+                (7, false),
+                (8, true)
+            ]
+        );
+        assert_eq!(processor.late_records().count(), 2);
+        assert_eq!(processor.replayed_records(), 3);
+        assert!(processor.checkpoints.len() <= 3);
+        processor.finish(&mut service).await;
+        assert_eq!(service.get_records().next().unwrap().available, 7.0);
+    }
+
+    #[tokio::test]
+    async fn reprocessing_reports_changed_outcomes() {
+        let mut processor = EventTimeProcessor::new(&ReorderConfig {
+            max_lateness_secs: 60,
+            late_policy: LatePolicy::Reprocess,
+            max_reprocess_history: 10,
+            checkpoint_interval: 2,
+        });
+        let buffer = SharedBuffer::default();
+        let audit_log = Arc::new(Mutex::new(AuditLog::new(buffer.clone())));
+        let mut service = Service::new();
+        service.set_audit_log(Some(audit_log.clone()));
+        let mut results = Vec::new();
+        for (r#type, tx, amount, timestamp) in [
+            (
+                InputFileRecordType::Deposit,
+                1,
+                10.0,
+                "2024-01-01T10:00:00Z",
+            ),
+            (
+                InputFileRecordType::Withdrawal,
+                2,
+                15.0,
+                "2024-01-01T10:10:00Z",
+            ),
+            (
+                InputFileRecordType::Deposit,
+                3,
+                10.0,
+                "2024-01-01T10:20:00Z",
+            ),
+            (
+                InputFileRecordType::Deposit,
+                4,
+                10.0,
+                "2024-01-01T10:05:00Z",
+            ),
+        ] {
+            let record = InputFileRecord {
+                r#type,
+                amount: Some(amount),
+                ..record(tx, Some(timestamp))
+            };
+            results.extend(processor.push(&mut service, record).await);
+        }
+        results.extend(processor.finish(&mut service).await);
+        let errors = results
+            .iter()
+            .map(|result| (result.tx, result.error.clone()))
+            .collect::<Vec<_>>();
+        // the withdrawal failed when first applied and succeeds after the late deposit
+        assert_eq!(
+            errors,
+            vec![
+                (1, None),
+                (2, Some("transaction stored as failed: 2".to_string())),
+                (4, None),
+                (2, None),
+                (3, None)
+            ]
+        );
+        audit_log.lock().unwrap().finish().unwrap();
+        let contents = buffer.contents();
+        let audited = contents
+            .lines()
+            .map(|line| {
+                let entry = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                let event = &entry["event"];
+                (
+                    event["kind"].clone(),
+                    event["tx"].clone(),
+                    event["status"].clone(),
+                )
+            })
+            .map(|(kind, tx, status)| format!("{} {tx} {status}", kind.as_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            audited,
+            vec![
+                "record 1 \"accepted\"",
+                "record 2 \"rejected\"",
+                "reprocessed 4 null",
+                "record 4 \"accepted\"",
+                "record 2 \"accepted\"",
+                "record 3 \"accepted\"",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_late_records_are_counted() {
+        let mut processor = EventTimeProcessor::new(&ReorderConfig {
+            max_lateness_secs: 60,
+            late_policy: LatePolicy::Reprocess,
+            max_reprocess_history: 2,
+            checkpoint_interval: 1,
+        });
+        let metrics = Arc::new(Metrics::default());
+        let mut service = Service::new();
+        service.set_metrics(Some(metrics.clone()));
+        for (tx, timestamp) in [
+            (1, "2024-01-01T10:00:00Z"),
+            (2, "2024-01-01T10:10:00Z"),
+            (3, "2024-01-01T10:20:00Z"),
+            (4, "2024-01-01T09:00:00Z"),
+            (5, "2024-01-01T10:05:00Z"),
+        ] {
+            processor
+                .push(&mut service, record(tx, Some(timestamp)))
+                .await;
+        }
+        processor.finish(&mut service).await;
+        // 4 is rejected without being handled, and still counted after 5 restores a checkpoint
+        let summary = service.run_summary();
+        assert_eq!((summary.records, summary.rejected), (5, 1));
+        let rendered = metrics.render();
+        assert!(rendered.contains("bank_records_total{type=\"deposit\",outcome=\"rejected\"} 1"));
+        assert!(rendered.contains("bank_rejections_total{reason=\"late record\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn reprocessing_replays_from_the_last_checkpoint() {
+        let mut processor = EventTimeProcessor::new(&ReorderConfig {
+            max_lateness_secs: 60,
+            late_policy: LatePolicy::Reprocess,
+            max_reprocess_history: 1000,
+            checkpoint_interval: 10,
+        });
+        let mut service = Service::new();
+        let time = |minutes: i64| {
+            "2024-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+                + Duration::seconds(minutes * 60)
+        };
+        for tx in 0..100 {
+            let record = InputFileRecord {
+                timestamp: Some(time(tx as i64 * 10)),
+                ..record(tx, None)
+            };
+            processor.push(&mut service, record).await;
+        }
+        let late = [(100, 975), (101, 965), (102, 955)];
+        for (tx, minutes) in late {
+            let record = InputFileRecord {
+                timestamp: Some(time(minutes)),
+                ..record(tx, None)
+            };
+            let results = processor.push(&mut service, record).await;
+            assert_eq!(results.len(), 1);
+            assert!(results[0].error.is_none());
+        }
+        assert_eq!(processor.late_records().count(), 3);
+        // each late record only replays the records after the checkpoint before it,
+        // not the hundred applied ones
+        assert!(
+            processor.replayed_records() <= 3 * 20,
+            "replayed {} records",
+            processor.replayed_records()
+        );
+        processor.finish(&mut service).await;
+        assert_eq!(service.get_records().next().unwrap().available, 103.0);
+        assert_eq!(service.run_summary().records, 103);
+    }
+}
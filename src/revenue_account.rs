@@ -0,0 +1,87 @@
+use crate::{
+    client_id::ClientId, transaction_id::TransactionId, transaction_info::TransactionType,
+};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Display, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeePostingStatus {
+    Posted,
+    Reversed,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct FeePosting {
+    pub id: TransactionId,
+    pub origin: TransactionId,
+    pub charged_for: TransactionType,
+    pub client: ClientId,
+    pub amount: f64,
+    pub status: FeePostingStatus,
+}
+
+/// Bank's own account that collects fee postings
+#[derive(Debug, Default)]
+pub struct RevenueAccount {
+    balance: f64,
+    postings: Vec<FeePosting>,
+    postings_by_origin: HashMap<TransactionId, Vec<usize>>,
+}
+
+impl RevenueAccount {
+    pub fn balance(&self) -> f64 {
+        self.balance
+    }
+
+    pub fn postings(&self) -> impl Iterator<Item = &FeePosting> {
+        self.postings.iter()
+    }
+
+    pub fn post(&mut self, posting: FeePosting) {
+        if posting.status == FeePostingStatus::Posted {
+            self.balance += posting.amount;
+        }
+        self.postings_by_origin
+            .entry(posting.origin)
+            .or_default()
+            .push(self.postings.len());
+        self.postings.push(posting);
+    }
+
+    /// Total of the active fees charged for `origin`, what reversing them would refund
+    pub fn posted(&self, origin: TransactionId, charged_for: TransactionType) -> f64 {
+        self.postings_by_origin
+            .get(&origin)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.postings[index])
+            .filter(|posting| {
+                posting.status == FeePostingStatus::Posted && posting.charged_for == charged_for
+            })
+            .map(|posting| posting.amount)
+            .sum()
+    }
+
+    /// Marks active fees charged for `origin` as reversed and returns them
+    pub fn reverse(
+        &mut self,
+        origin: TransactionId,
+        charged_for: TransactionType,
+    ) -> Vec<FeePosting> {
+        let Some(indices) = self.postings_by_origin.get(&origin) else {
+            return Vec::new();
+        };
+        let mut reversed = Vec::new();
+        for &index in indices {
+            let posting = &mut self.postings[index];
+            if posting.status == FeePostingStatus::Posted && posting.charged_for == charged_for {
+                posting.status = FeePostingStatus::Reversed;
+                self.balance -= posting.amount;
+                reversed.push(*posting);
+            }
+        }
+        reversed
+    }
+}
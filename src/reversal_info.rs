@@ -0,0 +1,7 @@
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone)]
+pub struct ReversalInfo {
+    pub reason: String,
+    pub reversed_at: DateTime<Utc>,
+}
@@ -0,0 +1,14 @@
+use derive_more::Display;
+use serde::Serialize;
+
+#[derive(Debug, Display, Default, Copy, Clone, Serialize, Eq, PartialEq)]
+#[display("records: {records}, rejected: {rejected}, replays: {replays}, conflicts: {conflicts}")]
+pub struct RunSummary {
+    pub records: u64,
+    /// Records that failed with an error, conflicts included
+    pub rejected: u64,
+    /// Identical retransmissions of already applied transactions, ignored
+    pub replays: u64,
+    /// Reuse of a transaction id with a different payload
+    pub conflicts: u64,
+}
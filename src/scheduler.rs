@@ -0,0 +1,166 @@
+use crate::input_file_reader::InputFileRecordType;
+use anyhow::{bail, Context};
+use chrono::{Days, Months, NaiveDate};
+use csv::{ReaderBuilder, Trim};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Display, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Recurrence {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// Standing order, or a one-off future-dated transaction when `recurrence` is not set
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduledInstruction {
+    pub id: u64,
+    pub r#type: InputFileRecordType,
+    pub client: u16,
+    pub amount: f64,
+    pub start: NaiveDate,
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// Last date an occurrence may fall on
+    #[serde(default)]
+    pub end: Option<NaiveDate>,
+}
+
+impl ScheduledInstruction {
+    /// Date of the `occurrence`-th run, counted from `start` so monthly orders keep their day
+    /// of month after a short month
+    pub fn occurrence_date(&self, occurrence: u32) -> Option<NaiveDate> {
+        let date = match self.recurrence {
+            None if occurrence == 0 => Some(self.start),
+            None => None,
+            Some(Recurrence::Daily) => self.start.checked_add_days(Days::new(occurrence as u64)),
+            Some(Recurrence::Weekly) => self
+                .start
+                .checked_add_days(Days::new(7 * occurrence as u64)),
+            Some(Recurrence::Monthly) => self.start.checked_add_months(Months::new(occurrence)),
+        }?;
+        self.end.is_none_or(|end| date <= end).then_some(date)
+    }
+
+    /// Date the instruction is due next, a pending retry comes before the next occurrence
+    pub fn due_date(&self, state: &ScheduleState) -> Option<NaiveDate> {
+        state
+            .retry_on
+            .or_else(|| self.occurrence_date(state.occurrence))
+    }
+}
+
+/// Progress of an instruction, kept between runs in the snapshot
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ScheduleState {
+    /// Index of the next occurrence to materialize
+    pub occurrence: u32,
+    /// Failed attempts of the current occurrence
+    pub attempts: u32,
+    pub retry_on: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Failed occurrences are retried this many times before they are abandoned
+    pub max_retries: u32,
+    pub retry_after_days: u32,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            retry_after_days: 1,
+        }
+    }
+}
+
+#[derive(Debug, Display, Serialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AttemptOutcome {
+    Applied,
+    /// Failed, retried after `retry_after_days`
+    Retrying,
+    /// Failed and out of retries, the instruction moves on to the next occurrence
+    Abandoned,
+}
+
+pub struct ScheduleReader {
+    path: String,
+}
+
+impl ScheduleReader {
+    pub fn new(path: String) -> ScheduleReader {
+        Self { path }
+    }
+
+    /// Like the client config, every row has to be valid
+    pub fn read_file(&self) -> anyhow::Result<Vec<ScheduledInstruction>> {
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_path(&self.path)
+            .context(format!("failed to open schedule file: {}", self.path))?;
+        let mut ids = HashSet::new();
+        let mut instructions = Vec::new();
+        for record in rdr.deserialize() {
+            let instruction: ScheduledInstruction =
+                record.context(format!("invalid schedule file: {}", self.path))?;
+            let id = instruction.id;
+            if !matches!(
+                instruction.r#type,
+                InputFileRecordType::Deposit | InputFileRecordType::Withdrawal
+            ) {
+                bail!(
+                    "unsupported type of scheduled instruction {id}: {}",
+                    instruction.r#type
+                );
+            }
+            if instruction.amount.is_nan() || instruction.amount <= 0f64 {
+                bail!(
+                    "invalid amount of scheduled instruction {id}: {}",
+                    instruction.amount
+                );
+            }
+            if !ids.insert(id) {
+                bail!("duplicate scheduled instruction: {id}");
+            }
+            instructions.push(instruction);
+        }
+        Ok(instructions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_schedule_and_occurrences() {
+        let instructions = ScheduleReader::new("tests/assets/schedule.csv".to_string())
+            .read_file()
+            .expect("schedule should be valid");
+        assert_eq!(instructions.len(), 2);
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+
+        let monthly = &instructions[0];
+        assert_eq!(monthly.recurrence, Some(Recurrence::Monthly));
+        assert_eq!(monthly.occurrence_date(1), Some(date("2024-02-29")));
+        assert_eq!(monthly.occurrence_date(2), Some(date("2024-03-31")));
+        assert_eq!(monthly.occurrence_date(5), None);
+
+        let one_off = &instructions[1];
+        assert_eq!(one_off.occurrence_date(0), Some(date("2024-02-15")));
+        assert_eq!(one_off.occurrence_date(1), None);
+        let retrying = ScheduleState {
+            occurrence: 0,
+            attempts: 1,
+            retry_on: Some(date("2024-02-16")),
+        };
+        assert_eq!(one_off.due_date(&retrying), Some(date("2024-02-16")));
+    }
+}
@@ -1,112 +1,1081 @@
 use crate::{
+    audit_log::{AuditEvent, AuditLog},
+    authorization_info::AuthorizationInfo,
+    authorization_policy::AuthorizationConfig,
+    client_config::ClientConfig,
     client_id::ClientId,
     client_info::ClientInfo,
+    client_table::ClientTable,
+    clock::{Clock, SystemClock},
+    config::Config,
+    dispute_info::DisputeInfo,
+    dispute_policy::DisputeConfig,
+    expiry_queue::ExpiryQueue,
+    fee_schedule::FeeSchedule,
+    fraud_rules::{RuleAction, RuleEngine, RuleHit, RuleRejection},
     input_file_reader::{InputFileRecord, InputFileRecordType},
-    output_record::{OutputRecord, OutputRecordProvider},
+    interest::{BalanceChanges, InterestConfig, InterestLedger, InterestPosting},
+    invariants::{
+        ClientFlows, InvariantCheck, InvariantConfig, InvariantViolation, Ledger, MoneyFlows,
+    },
+    limits::{LimitsConfig, VelocityTracker, WithdrawalLimits},
+    metrics::Metrics,
+    output_record::{
+        FeeRecord, FlaggedRecord, HistoryRecord, InterestRecord, OutputRecord,
+        OutputRecordProvider, ScheduledRecord, StatementRecord,
+    },
+    revenue_account::{FeePosting, FeePostingStatus, RevenueAccount},
+    reversal_info::ReversalInfo,
+    run_summary::RunSummary,
+    scheduler::{AttemptOutcome, ScheduleConfig, ScheduleState, ScheduledInstruction},
+    snapshot::{
+        AuthorizationSnapshot, BalanceChangeSnapshot, ClientSnapshot, DisputeSnapshot,
+        FeePostingSnapshot, ReversalSnapshot, ScheduleSnapshot, Snapshot, TransactionSnapshot,
+        VelocitySnapshot, SNAPSHOT_VERSION,
+    },
     transaction_id::TransactionId,
     transaction_info::{TransactionInfo, TransactionStatus, TransactionType},
+    transaction_store::TransactionStore,
 };
 use anyhow::{bail, Context};
-use log::{debug, error, warn};
-use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, Utc};
+use derive_more::Display;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, instrument, warn};
+
+/// Largest amount a record may move, balances keep four decimal places at this magnitude
+// improvement: use a decimal type for amounts
+pub const MAX_AMOUNT: f64 = 1e11;
+
+/// Records between updates of the metrics gauges, which walk every client
+const METRICS_REFRESH_INTERVAL: u64 = 4096;
+
+#[derive(Debug, Display)]
+#[display("conflicting duplicate transaction id: {transaction_id}")]
+pub struct TransactionConflict {
+    pub transaction_id: TransactionId,
+}
+
+impl std::error::Error for TransactionConflict {}
+
+/// Engine state to go back to: the ledger as saved to a snapshot, and the run-level state built
+/// up to that point
+#[derive(Clone)]
+pub struct Checkpoint {
+    snapshot: Snapshot,
+    rule_engine: RuleEngine,
+    flows: MoneyFlows,
+    client_flows: ClientFlows,
+    run_summary: RunSummary,
+    flagged_transactions: usize,
+    invariant_violations: usize,
+}
+
+struct FlaggedTransaction {
+    r#type: InputFileRecordType,
+    client: u64,
+    tx: u64,
+    hit: RuleHit,
+}
 
 pub struct Service {
-    transaction_table: HashMap<TransactionId, TransactionInfo>,
-    client_table: HashMap<ClientId, ClientInfo>,
-    dispute_table: HashMap<TransactionId, ClientId>,
+    transaction_table: TransactionStore,
+    client_table: ClientTable,
+    dispute_table: HashMap<TransactionId, DisputeInfo>,
+    /// Open disputes by the time they were opened, only kept when disputes expire
+    dispute_expiries: ExpiryQueue,
     chargeback_table: HashSet<TransactionId>,
+    reversal_table: HashMap<TransactionId, ReversalInfo>,
+    authorization_table: HashMap<TransactionId, AuthorizationInfo>,
+    /// Open authorizations by the time they were made, only kept when authorizations expire
+    authorization_expiries: ExpiryQueue,
+    fee_schedule: FeeSchedule,
+    revenue_account: RevenueAccount,
+    interest_config: InterestConfig,
+    interest_ledger: InterestLedger,
+    /// Changes of available balances since the last accrual, so that every day accrues
+    /// interest on its own end of day balance
+    balance_changes: BalanceChanges,
+    client_configs: HashMap<ClientId, ClientConfig>,
+    limits_config: LimitsConfig,
+    velocity_tracker: VelocityTracker,
+    rule_engine: RuleEngine,
+    flagged_transactions: Vec<FlaggedTransaction>,
+    dispute_config: DisputeConfig,
+    authorization_config: AuthorizationConfig,
+    schedule_config: ScheduleConfig,
+    schedule_state: HashMap<u64, ScheduleState>,
+    scheduled_records: Vec<ScheduledRecord>,
+    invariant_config: InvariantConfig,
+    flows: MoneyFlows,
+    /// Tracked apart from the balances, the reported totals are checked against them
+    client_flows: ClientFlows,
+    invariant_violations: Vec<InvariantViolation>,
+    clock: Box<dyn Clock>,
+    run_summary: RunSummary,
+    /// Records rejected without handling them, such as late records. Not part of checkpoints,
+    /// they are not replayed after a restore
+    unhandled_rejections: u64,
+    /// Only collected when set, timing every record is not free
+    metrics: Option<Arc<Metrics>>,
+    /// Shared with the services replacing this one, so the chain goes on after a rollback
+    audit_log: Option<Arc<Mutex<AuditLog>>>,
+    // system generated postings take ids from the top of the range, counting down
+    // improvement: reserve the range explicitly so input files cannot collide with it
+    next_system_transaction_id: u64,
+}
+
+impl Default for Service {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Service {
     pub fn new() -> Service {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Service {
+        Self::with_clock(config, Box::new(SystemClock))
+    }
+
+    pub fn with_clock(config: Config, clock: Box<dyn Clock>) -> Service {
         Self {
             transaction_table: Default::default(),
             client_table: Default::default(),
             dispute_table: Default::default(),
+            dispute_expiries: Default::default(),
             chargeback_table: Default::default(),
+            reversal_table: Default::default(),
+            authorization_table: Default::default(),
+            authorization_expiries: Default::default(),
+            fee_schedule: config.fees,
+            revenue_account: Default::default(),
+            interest_config: config.interest,
+            interest_ledger: Default::default(),
+            balance_changes: Default::default(),
+            client_configs: config.clients,
+            velocity_tracker: VelocityTracker::new(config.limits.window_hours),
+            limits_config: config.limits,
+            rule_engine: RuleEngine::new(&config.rules),
+            flagged_transactions: Default::default(),
+            dispute_config: config.disputes,
+            authorization_config: config.authorizations,
+            schedule_config: config.schedule,
+            schedule_state: Default::default(),
+            scheduled_records: Default::default(),
+            invariant_config: config.invariants,
+            flows: Default::default(),
+            client_flows: Default::default(),
+            invariant_violations: Default::default(),
+            clock,
+            run_summary: Default::default(),
+            unhandled_rejections: 0,
+            metrics: None,
+            audit_log: None,
+            next_system_transaction_id: u64::MAX,
+        }
+    }
+
+    /// Checks the accounting invariants and records every violation
+    pub fn check_invariants(&mut self, checked_after: &str) {
+        let ledger = Ledger {
+            clients: &self.client_table,
+            client_flows: &self.client_flows,
+            transactions: &self.transaction_table,
+            disputes: &self.dispute_table,
+            flows: self.flows,
+            fee_revenue: self.revenue_account.balance(),
+            interest_paid: self.interest_ledger.total_paid(),
+        };
+        for violation in ledger.check(checked_after) {
+            error!(
+                "invariant {} violated after {checked_after}: {}",
+                violation.invariant, violation.detail
+            );
+            self.invariant_violations.push(violation);
+        }
+    }
+
+    /// Runs the end of run invariant check when configured
+    pub fn finish_run(&mut self) {
+        if self.invariant_config.check == InvariantCheck::EndOfRun {
+            self.check_invariants("end of run");
+        }
+        self.refresh_metrics();
+    }
+
+    pub fn audit_log(&self) -> Option<&Arc<Mutex<AuditLog>>> {
+        self.audit_log.as_ref()
+    }
+
+    /// Audit log to append state changes to, possibly shared with the service this one replaces
+    pub fn set_audit_log(&mut self, audit_log: Option<Arc<Mutex<AuditLog>>>) {
+        self.audit_log = audit_log;
+    }
+
+    /// Appends the event to the audit log, the event is only built when there is one
+    pub fn audit(&self, event: impl FnOnce() -> AuditEvent) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log
+                .lock()
+                .expect("audit log lock poisoned")
+                .append(self.clock.now(), &event());
+        }
+    }
+
+    pub fn metrics(&self) -> Option<&Arc<Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// Metrics to collect into, possibly shared with the service this one replaces
+    pub fn set_metrics(&mut self, metrics: Option<Arc<Metrics>>) {
+        self.metrics = metrics;
+    }
+
+    /// Updates the gauges from the current state
+    pub fn refresh_metrics(&self) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        let locked_clients = self
+            .client_table
+            .values()
+            .filter(|info| info.is_locked)
+            .count();
+        let held_funds = self.client_table.values().map(|info| info.on_hold).sum();
+        metrics.set_state(self.dispute_table.len(), locked_clients, held_funds);
+    }
+
+    pub fn invariant_violations(&self) -> impl Iterator<Item = &InvariantViolation> {
+        self.invariant_violations.iter()
+    }
+
+    pub fn is_locked(&self, client: u64) -> bool {
+        self.client_table
+            .get(&ClientId::new(client as u16))
+            .is_some_and(|info| info.is_locked)
+    }
+
+    pub fn run_summary(&self) -> RunSummary {
+        RunSummary {
+            records: self.run_summary.records + self.unhandled_rejections,
+            rejected: self.run_summary.rejected + self.unhandled_rejections,
+            ..self.run_summary
+        }
+    }
+
+    /// Counts and audits a record rejected without handling it, like handled records are
+    pub fn reject(&mut self, record: &InputFileRecord, rejection: &str) {
+        self.unhandled_rejections += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.record(record.r#type, Some(rejection), Duration::ZERO);
+        }
+        self.audit(|| AuditEvent::record(record, Some(rejection)));
+    }
+
+    pub fn revenue_account(&self) -> &RevenueAccount {
+        &self.revenue_account
+    }
+
+    pub fn export_snapshot(&self) -> Snapshot {
+        let mut clients = self
+            .client_table
+            .iter()
+            .map(|(client_id, info)| ClientSnapshot {
+                client: client_id.value(),
+                available: info.available,
+                held: info.on_hold,
+                authorized: info.authorized,
+                locked: info.is_locked,
+            })
+            .collect::<Vec<_>>();
+        clients.sort_by_key(|client| client.client);
+        let mut transactions = self
+            .transaction_table
+            .iter()
+            .map(|(transaction_id, info)| TransactionSnapshot {
+                tx: transaction_id.value(),
+                r#type: info.r#type,
+                client: info.client.value(),
+                amount: info.amount,
+                status: info.status,
+                timestamp: info.timestamp,
+            })
+            .collect::<Vec<_>>();
+        transactions.sort_by_key(|transaction| transaction.tx);
+        let mut disputes = self
+            .dispute_table
+            .iter()
+            .map(|(transaction_id, dispute)| DisputeSnapshot {
+                tx: transaction_id.value(),
+                client: dispute.client.value(),
+                opened_at: dispute.opened_at,
+            })
+            .collect::<Vec<_>>();
+        disputes.sort_by_key(|dispute| dispute.tx);
+        let mut chargeback_eligible = self
+            .chargeback_table
+            .iter()
+            .map(TransactionId::value)
+            .collect::<Vec<_>>();
+        chargeback_eligible.sort();
+        let fee_postings = self
+            .revenue_account
+            .postings()
+            .map(|posting| FeePostingSnapshot {
+                id: posting.id.value(),
+                origin: posting.origin.value(),
+                charged_for: posting.charged_for,
+                client: posting.client.value(),
+                amount: posting.amount,
+                status: posting.status,
+            })
+            .collect();
+        let mut reversals = self
+            .reversal_table
+            .iter()
+            .map(|(transaction_id, reversal)| ReversalSnapshot {
+                tx: transaction_id.value(),
+                reason: reversal.reason.clone(),
+                reversed_at: reversal.reversed_at,
+            })
+            .collect::<Vec<_>>();
+        reversals.sort_by_key(|reversal| reversal.tx);
+        let mut authorizations = self
+            .authorization_table
+            .iter()
+            .map(|(transaction_id, authorization)| AuthorizationSnapshot {
+                tx: transaction_id.value(),
+                client: authorization.client.value(),
+                amount: authorization.amount,
+                authorized_at: authorization.authorized_at,
+            })
+            .collect::<Vec<_>>();
+        authorizations.sort_by_key(|authorization| authorization.tx);
+        let mut schedule = self
+            .schedule_state
+            .iter()
+            .map(|(instruction, state)| ScheduleSnapshot {
+                instruction: *instruction,
+                occurrence: state.occurrence,
+                attempts: state.attempts,
+                retry_on: state.retry_on,
+            })
+            .collect::<Vec<_>>();
+        schedule.sort_by_key(|state| state.instruction);
+        let mut velocity = self
+            .velocity_tracker
+            .withdrawals()
+            .map(|(client_id, withdrawn_at, amount)| VelocitySnapshot {
+                client: client_id.value(),
+                withdrawn_at,
+                amount,
+            })
+            .collect::<Vec<_>>();
+        // stable, withdrawals of a client stay in time order
+        velocity.sort_by_key(|withdrawal| withdrawal.client);
+        let mut balance_changes = self
+            .balance_changes
+            .iter()
+            .map(|(client_id, date, change)| BalanceChangeSnapshot {
+                client: client_id.value(),
+                date,
+                change,
+            })
+            .collect::<Vec<_>>();
+        balance_changes.sort_by_key(|change| (change.client, change.date));
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            clients,
+            transactions,
+            disputes,
+            chargeback_eligible,
+            fee_postings,
+            reversals,
+            authorizations,
+            schedule,
+            velocity,
+            balance_changes,
+            last_interest_accrual: self.interest_ledger.last_accrual_date,
+            next_system_transaction_id: self.next_system_transaction_id,
+        }
+    }
+
+    /// Replaces the engine state with the snapshot, configuration is kept
+    pub fn import_snapshot(&mut self, snapshot: Snapshot) {
+        self.client_table = snapshot
+            .clients
+            .into_iter()
+            .map(|client| {
+                (
+                    ClientId::new(client.client),
+                    ClientInfo {
+                        on_hold: client.held,
+                        authorized: client.authorized,
+                        available: client.available,
+                        is_locked: client.locked,
+                    },
+                )
+            })
+            .collect();
+        self.transaction_table = snapshot
+            .transactions
+            .into_iter()
+            .map(|transaction| {
+                (
+                    TransactionId::new(transaction.tx),
+                    TransactionInfo {
+                        r#type: transaction.r#type,
+                        client: ClientId::new(transaction.client),
+                        amount: transaction.amount,
+                        status: transaction.status,
+                        timestamp: transaction.timestamp,
+                    },
+                )
+            })
+            .collect();
+        self.dispute_table = snapshot
+            .disputes
+            .into_iter()
+            .map(|dispute| {
+                (
+                    TransactionId::new(dispute.tx),
+                    DisputeInfo {
+                        client: ClientId::new(dispute.client),
+                        opened_at: dispute.opened_at,
+                    },
+                )
+            })
+            .collect();
+        self.dispute_expiries = Default::default();
+        let disputes = self
+            .dispute_table
+            .iter()
+            .map(|(transaction_id, dispute)| (*transaction_id, dispute.opened_at))
+            .collect::<Vec<_>>();
+        for (transaction_id, opened_at) in disputes {
+            self.queue_dispute_expiry(transaction_id, opened_at);
+        }
+        self.chargeback_table = snapshot
+            .chargeback_eligible
+            .into_iter()
+            .map(TransactionId::new)
+            .collect();
+        self.reversal_table = snapshot
+            .reversals
+            .into_iter()
+            .map(|reversal| {
+                (
+                    TransactionId::new(reversal.tx),
+                    ReversalInfo {
+                        reason: reversal.reason,
+                        reversed_at: reversal.reversed_at,
+                    },
+                )
+            })
+            .collect();
+        self.authorization_table = snapshot
+            .authorizations
+            .into_iter()
+            .map(|authorization| {
+                (
+                    TransactionId::new(authorization.tx),
+                    AuthorizationInfo {
+                        client: ClientId::new(authorization.client),
+                        amount: authorization.amount,
+                        authorized_at: authorization.authorized_at,
+                    },
+                )
+            })
+            .collect();
+        self.authorization_expiries = Default::default();
+        let authorizations = self
+            .authorization_table
+            .iter()
+            .map(|(transaction_id, authorization)| (*transaction_id, authorization.authorized_at))
+            .collect::<Vec<_>>();
+        for (transaction_id, authorized_at) in authorizations {
+            self.queue_authorization_expiry(transaction_id, authorized_at);
+        }
+        self.schedule_state = snapshot
+            .schedule
+            .into_iter()
+            .map(|state| {
+                (
+                    state.instruction,
+                    ScheduleState {
+                        occurrence: state.occurrence,
+                        attempts: state.attempts,
+                        retry_on: state.retry_on,
+                    },
+                )
+            })
+            .collect();
+        self.revenue_account = Default::default();
+        for posting in snapshot.fee_postings {
+            self.revenue_account.post(FeePosting {
+                id: TransactionId::new(posting.id),
+                origin: TransactionId::new(posting.origin),
+                charged_for: posting.charged_for,
+                client: ClientId::new(posting.client),
+                amount: posting.amount,
+                status: posting.status,
+            });
+        }
+        self.velocity_tracker = VelocityTracker::new(self.limits_config.window_hours);
+        for withdrawal in snapshot.velocity {
+            self.velocity_tracker.record(
+                ClientId::new(withdrawal.client),
+                withdrawal.amount,
+                withdrawal.withdrawn_at,
+            );
+        }
+        self.balance_changes = Default::default();
+        for change in snapshot.balance_changes {
+            self.balance_changes
+                .record(ClientId::new(change.client), change.date, change.change);
+        }
+        self.interest_ledger = Default::default();
+        self.interest_ledger.last_accrual_date = snapshot.last_interest_accrual;
+        self.next_system_transaction_id = snapshot.next_system_transaction_id;
+        self.flows = MoneyFlows {
+            opening: self
+                .client_table
+                .values()
+                .map(ClientInfo::total)
+                .sum::<f64>()
+                + self.revenue_account.balance(),
+            ..Default::default()
+        };
+        self.client_flows = Default::default();
+        for (client_id, info) in self.client_table.iter() {
+            self.client_flows.record(client_id, info.total());
+        }
+        for info in self.transaction_table.values() {
+            if info.status != TransactionStatus::Failure {
+                self.client_flows.moved(info.client, info.amount);
+            }
+        }
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            snapshot: self.export_snapshot(),
+            rule_engine: self.rule_engine.clone(),
+            flows: self.flows,
+            client_flows: self.client_flows.clone(),
+            run_summary: self.run_summary,
+            flagged_transactions: self.flagged_transactions.len(),
+            invariant_violations: self.invariant_violations.len(),
+        }
+    }
+
+    /// Goes back to a checkpoint taken from this service, as if the records handled since had
+    /// never been. Only the metrics and the audit log keep them
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        // records do not post interest, the postings made so far stay
+        let interest_ledger = std::mem::take(&mut self.interest_ledger);
+        self.import_snapshot(checkpoint.snapshot);
+        self.interest_ledger = interest_ledger;
+        self.rule_engine = checkpoint.rule_engine;
+        self.flows = checkpoint.flows;
+        self.client_flows = checkpoint.client_flows;
+        self.run_summary = checkpoint.run_summary;
+        self.flagged_transactions
+            .truncate(checkpoint.flagged_transactions);
+        self.invariant_violations
+            .truncate(checkpoint.invariant_violations);
+    }
+
+    /// Accrues daily interest on the available balance at the end of every day since the
+    /// previous accrual (or of `date` only on the first run) and posts it as one deposit per
+    /// client. Returns the number of postings made
+    pub fn accrue_interest(&mut self, date: NaiveDate) -> anyhow::Result<usize> {
+        let first_date = match self.interest_ledger.last_accrual_date {
+            Some(last_date) if last_date >= date => {
+                bail!("interest already accrued up to {last_date}")
+            }
+            Some(last_date) => last_date
+                .succ_opt()
+                .context(format!("no date after {last_date}"))?,
+            None => date,
+        };
+        let client_ids = self.client_table.keys().collect::<Vec<_>>();
+        let mut postings_count = 0;
+        for client_id in client_ids {
+            let client_info = &self.client_table[&client_id];
+            if client_info.is_locked {
+                debug!("skip interest for locked client: {client_id}");
+                continue;
+            }
+            let current = client_info.available;
+            let accruals = first_date
+                .iter_days()
+                .take_while(|day| *day <= date)
+                .map(|day| {
+                    (
+                        day,
+                        self.balance_changes.balance_at(client_id, day, current),
+                    )
+                })
+                .filter(|(_, balance)| *balance > 0f64)
+                .map(|(day, balance)| self.interest_config.daily_interest(day, balance))
+                .collect::<Vec<_>>();
+            let amount = accruals.iter().map(|accrual| accrual.amount).sum::<f64>();
+            if amount <= 0f64 {
+                continue;
+            }
+            let posting_id = self.next_system_transaction_id();
+            let client_info = self
+                .client_table
+                .get_mut(&client_id)
+                .expect("client id should exist");
+            client_info.available += amount;
+            self.client_flows.record(client_id, amount);
+            debug!("interest {posting_id} posted for client {client_id}: {amount}");
+            self.audit(|| AuditEvent::InterestPosted {
+                tx: posting_id.value(),
+                client: client_id.value() as u64,
+                amount,
+            });
+            self.interest_ledger.post(InterestPosting {
+                id: posting_id,
+                client: client_id,
+                accruals,
+            });
+            postings_count += 1;
+        }
+        self.interest_ledger.last_accrual_date = Some(date);
+        self.balance_changes.accrued(date);
+        Ok(postings_count)
+    }
+
+    /// Materializes every occurrence of the instructions due up to `until` in date order and
+    /// processes it as a regular transaction. Returns the number of attempts made
+    pub async fn run_schedule(
+        &mut self,
+        instructions: &[ScheduledInstruction],
+        until: NaiveDate,
+    ) -> usize {
+        let mut attempts_count = 0;
+        loop {
+            let next = instructions
+                .iter()
+                .filter_map(|instruction| {
+                    let state = self
+                        .schedule_state
+                        .get(&instruction.id)
+                        .copied()
+                        .unwrap_or_default();
+                    instruction
+                        .due_date(&state)
+                        .map(|date| (date, instruction.id, instruction))
+                })
+                .filter(|(date, _, _)| *date <= until)
+                .min_by_key(|(date, id, _)| (*date, *id));
+            let Some((date, _, instruction)) = next else {
+                break;
+            };
+            self.run_instruction(instruction, date).await;
+            attempts_count += 1;
+        }
+        attempts_count
+    }
+
+    async fn run_instruction(&mut self, instruction: &ScheduledInstruction, date: NaiveDate) {
+        let transaction_id = self.next_system_transaction_id();
+        let record = InputFileRecord {
+            r#type: instruction.r#type,
+            client: instruction.client as u64,
+            tx: transaction_id.value(),
+            amount: Some(instruction.amount),
+            timestamp: Some(date.and_time(NaiveTime::MIN).and_utc()),
+            reason: None,
+        };
+        if let Err(err) = self.handle(&record).await {
+            error!("scheduled transaction failure: {err}");
+        }
+        // records ignored for locked clients leave no transaction behind
+        let applied = self
+            .transaction_table
+            .get(&transaction_id)
+            .is_some_and(|info| info.status == TransactionStatus::Success);
+        let max_retries = self.schedule_config.max_retries;
+        let retry_after_days = self.schedule_config.retry_after_days.max(1);
+        let state = self.schedule_state.entry(instruction.id).or_default();
+        let attempt = state.attempts + 1;
+        let outcome = if applied {
+            AttemptOutcome::Applied
+        } else if state.attempts < max_retries {
+            AttemptOutcome::Retrying
+        } else {
+            AttemptOutcome::Abandoned
+        };
+        match outcome {
+            AttemptOutcome::Retrying => {
+                state.attempts += 1;
+                state.retry_on = date.checked_add_days(Days::new(retry_after_days as u64));
+            }
+            AttemptOutcome::Applied | AttemptOutcome::Abandoned => {
+                state.occurrence += 1;
+                state.attempts = 0;
+                state.retry_on = None;
+            }
+        }
+        if outcome == AttemptOutcome::Abandoned {
+            warn!(
+                "scheduled instruction {} abandoned for {date} after {attempt} attempts",
+                instruction.id
+            );
+        }
+        self.scheduled_records.push(ScheduledRecord {
+            instruction: instruction.id,
+            tx: transaction_id.value(),
+            r#type: instruction.r#type,
+            client: instruction.client as u64,
+            amount: instruction.amount,
+            date,
+            attempt,
+            outcome,
+        });
+    }
+
+    fn credit_limit(&self, client_id: ClientId) -> f64 {
+        self.client_configs
+            .get(&client_id)
+            .map_or(0f64, |config| config.credit_limit)
+    }
+
+    fn withdrawal_limits(&self, client_id: ClientId) -> WithdrawalLimits {
+        let defaults = self.limits_config.defaults();
+        self.client_configs
+            .get(&client_id)
+            .map_or(defaults, |config| config.limits.or(defaults))
+    }
+
+    fn queue_dispute_expiry(&mut self, transaction_id: TransactionId, opened_at: DateTime<Utc>) {
+        if self.dispute_config.expire_after_days.is_some() {
+            self.dispute_expiries.push(opened_at, transaction_id);
+        }
+    }
+
+    /// Resolves disputes that stayed open longer than allowed
+    fn expire_disputes(&mut self, now: DateTime<Utc>) {
+        if self.dispute_config.expire_after_days.is_none() {
+            return;
+        }
+        let config = &self.dispute_config;
+        let mut expired = self
+            .dispute_expiries
+            .pop_expired(|opened_at| config.is_expired(opened_at, now))
+            .into_iter()
+            .filter_map(|(opened_at, transaction_id)| {
+                // disputes settled before they expired are still queued
+                let dispute = self.dispute_table.get(&transaction_id)?;
+                (dispute.opened_at == opened_at).then_some((transaction_id, dispute.client))
+            })
+            .collect::<Vec<_>>();
+        expired.sort();
+        for (transaction_id, client_id) in expired {
+            warn!("dispute expired, resolving transaction: {transaction_id}");
+            let available = self.available(client_id);
+            let outcome = self.process_resolve(transaction_id, client_id);
+            self.record_balance_change(client_id, available, now);
+            match outcome {
+                Ok(()) => self.audit(|| AuditEvent::DisputeExpired {
+                    tx: transaction_id.value(),
+                    client: client_id.value() as u64,
+                }),
+                Err(err) => error!("failed to resolve expired dispute {transaction_id}: {err}"),
+            }
+        }
+    }
+
+    fn queue_authorization_expiry(
+        &mut self,
+        transaction_id: TransactionId,
+        authorized_at: DateTime<Utc>,
+    ) {
+        if self.authorization_config.expire_after_hours.is_some() {
+            self.authorization_expiries
+                .push(authorized_at, transaction_id);
+        }
+    }
+
+    fn expire_authorizations(&mut self, now: DateTime<Utc>) {
+        if self.authorization_config.expire_after_hours.is_none() {
+            return;
+        }
+        let config = &self.authorization_config;
+        let mut expired = self
+            .authorization_expiries
+            .pop_expired(|authorized_at| config.is_expired(authorized_at, now))
+            .into_iter()
+            .filter(|(authorized_at, transaction_id)| {
+                // captured and voided authorizations are still queued
+                self.authorization_table
+                    .get(transaction_id)
+                    .is_some_and(|authorization| authorization.authorized_at == *authorized_at)
+            })
+            .map(|(_, transaction_id)| transaction_id)
+            .collect::<Vec<_>>();
+        expired.sort();
+        for transaction_id in expired {
+            warn!("authorization expired, releasing transaction: {transaction_id}");
+            let client_id = self.authorization_table[&transaction_id].client;
+            let available = self.available(client_id);
+            let outcome = self.release_authorization(transaction_id, TransactionStatus::Expired);
+            self.record_balance_change(client_id, available, now);
+            match outcome {
+                Ok(()) => self.audit(|| AuditEvent::AuthorizationExpired {
+                    tx: transaction_id.value(),
+                }),
+                Err(err) => {
+                    error!("failed to release expired authorization {transaction_id}: {err}")
+                }
+            }
+        }
+    }
+
+    fn available(&self, client_id: ClientId) -> f64 {
+        self.client_table
+            .get(&client_id)
+            .map_or(0f64, |info| info.available)
+    }
+
+    /// Dates the change of the available balance since it was `available` for interest accrual
+    fn record_balance_change(&mut self, client_id: ClientId, available: f64, time: DateTime<Utc>) {
+        // nothing accrues without rates, no need to track
+        if self.interest_config.rates.is_empty() {
+            return;
+        }
+        let change = self.available(client_id) - available;
+        self.balance_changes
+            .record(client_id, time.date_naive(), change);
+    }
+
+    fn next_system_transaction_id(&mut self) -> TransactionId {
+        let transaction_id = TransactionId::new(self.next_system_transaction_id);
+        self.next_system_transaction_id -= 1;
+        transaction_id
+    }
+
+    fn charge_fee(
+        &mut self,
+        origin: TransactionId,
+        client_id: ClientId,
+        charged_for: TransactionType,
+        amount: f64,
+    ) -> anyhow::Result<()> {
+        let fee = self.fee_schedule.fee_for(charged_for, amount);
+        if fee <= 0f64 {
+            return Ok(());
+        }
+        let credit_limit = self.credit_limit(client_id);
+        let client_info = self
+            .client_table
+            .get_mut(&client_id)
+            .context(format!("client id not found: {client_id}"))?;
+        if client_info.available + credit_limit < fee {
+            warn!(
+                "{charged_for} fee not charged for transaction {origin}, available: {}, requested: {fee}",
+                client_info.available
+            );
+            return Ok(());
+        }
+        client_info.available -= fee;
+        self.client_flows.record(client_id, -fee);
+        let fee_id = self.next_system_transaction_id();
+        debug!("{charged_for} fee {fee_id} charged for transaction {origin}: {fee}");
+        self.revenue_account.post(FeePosting {
+            id: fee_id,
+            origin,
+            charged_for,
+            client: client_id,
+            amount: fee,
+            status: FeePostingStatus::Posted,
+        });
+        Ok(())
+    }
+
+    fn reverse_fees(
+        &mut self,
+        origin: TransactionId,
+        charged_for: TransactionType,
+    ) -> anyhow::Result<()> {
+        for posting in self.revenue_account.reverse(origin, charged_for) {
+            let client_info = self
+                .client_table
+                .get_mut(&posting.client)
+                .context(format!("client id not found: {}", posting.client))?;
+            debug!("fee {} reversed for transaction {origin}", posting.id);
+            client_info.available += posting.amount;
+            self.client_flows.record(posting.client, posting.amount);
+        }
+        Ok(())
+    }
+
+    /// Returns true for an identical retransmission of a known transaction,
+    /// fails if the transaction id is already used by a different payload
+    fn is_replay(
+        &mut self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        r#type: TransactionType,
+        amount: f64,
+    ) -> anyhow::Result<bool> {
+        let Some(transaction_info) = self.transaction_table.get(&transaction_id) else {
+            return Ok(false);
+        };
+        if transaction_info.r#type != r#type
+            || transaction_info.client != client_id
+            || transaction_info.amount != amount
+        {
+            return Err(TransactionConflict { transaction_id }.into());
         }
+        debug!("ignore replay of transaction: {transaction_id}");
+        self.run_summary.replays += 1;
+        Ok(true)
+    }
+
+    fn fail_transaction(&mut self, transaction_id: TransactionId) {
+        self.transaction_table
+            .set_status(&transaction_id, TransactionStatus::Failure);
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process_deposit(
         &mut self,
         transaction_id: TransactionId,
         client_id: ClientId,
         amount: Option<f64>,
+        timestamp: DateTime<Utc>,
     ) -> anyhow::Result<()> {
-        if self.transaction_table.contains_key(&transaction_id) {
-            bail!("duplicate transaction id: {transaction_id}");
-        }
         let amount = amount.context("deposit transaction missing 'amount' field")?;
+        // never stored, a snapshot could not hold it
+        if !amount.is_finite() {
+            bail!("cannot deposit amount out of range: {amount}");
+        }
+        if self.is_replay(transaction_id, client_id, TransactionType::Deposit, amount)? {
+            return Ok(());
+        }
         self.transaction_table.insert(
             transaction_id,
             TransactionInfo {
                 r#type: TransactionType::Deposit,
                 client: client_id,
-                amount: Some(amount),
+                amount,
                 status: TransactionStatus::Success,
+                timestamp: Some(timestamp),
             },
         );
+        if amount > MAX_AMOUNT {
+            self.fail_transaction(transaction_id);
+            bail!("cannot deposit amount out of range: {amount}");
+        }
         if amount < 0f64 {
-            let transaction_info = self
-                .transaction_table
-                .get_mut(&transaction_id)
-                .expect("transaction id should exist");
-            transaction_info.status = TransactionStatus::Failure;
+            self.fail_transaction(transaction_id);
             bail!("cannot deposit negative amount: {amount}");
         }
-        let client_info = self.client_table.entry(client_id).or_default();
+        let client_info = self.client_table.get_or_default(client_id);
         // improvement: replace with checked_add/checked_sub
         client_info.available += amount;
-        Ok(())
+        self.flows.deposits += amount;
+        self.client_flows.record(client_id, amount);
+        self.charge_fee(transaction_id, client_id, TransactionType::Deposit, amount)
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process_withdrawal(
         &mut self,
         transaction_id: TransactionId,
         client_id: ClientId,
         amount: Option<f64>,
+        timestamp: DateTime<Utc>,
     ) -> anyhow::Result<()> {
-        if self.transaction_table.contains_key(&transaction_id) {
-            bail!("duplicate transaction id: {transaction_id}");
-        }
         let amount = amount.context("withdrawal transaction missing 'amount' field")?;
+        // never stored, a snapshot could not hold it
+        if !amount.is_finite() {
+            bail!("cannot withdraw amount out of range: {amount}");
+        }
+        if self.is_replay(
+            transaction_id,
+            client_id,
+            TransactionType::Withdrawal,
+            amount,
+        )? {
+            return Ok(());
+        }
+        let credit_limit = self.credit_limit(client_id);
+        let limits = self.withdrawal_limits(client_id);
         self.transaction_table.insert(
             transaction_id,
             TransactionInfo {
                 r#type: TransactionType::Withdrawal,
                 client: client_id,
-                amount: Some(amount),
+                amount,
                 status: TransactionStatus::Success,
+                timestamp: None,
             },
         );
-        let transaction_info = self
-            .transaction_table
-            .get_mut(&transaction_id)
-            .expect("transaction id should exist");
+        if amount > MAX_AMOUNT {
+            self.fail_transaction(transaction_id);
+            bail!("cannot withdraw amount out of range: {amount}");
+        }
         if amount < 0f64 {
-            transaction_info.status = TransactionStatus::Failure;
+            self.fail_transaction(transaction_id);
             bail!("cannot withdraw negative amount: {amount}");
         }
-        let client_info = self
-            .client_table
-            .get_mut(&client_id)
-            .context(format!("client id not found: {client_id}"))?;
-        if client_info.available < amount {
+        if let Err(violation) = self
+            .velocity_tracker
+            .check(client_id, amount, timestamp, limits)
+        {
+            self.fail_transaction(transaction_id);
+            return Err(violation.into());
+        }
+        let available = match self.client_table.get(&client_id) {
+            Some(client_info) => client_info.available,
+            // a client with a credit limit may overdraw before depositing anything,
+            // the account is only opened once the withdrawal succeeds
+            None if credit_limit > 0f64 => ClientInfo::default().available,
+            None => bail!("client id not found: {client_id}"),
+        };
+        let fee = self
+            .fee_schedule
+            .fee_for(TransactionType::Withdrawal, amount);
+        // withdrawals may overdraw the account down to the agreed credit limit
+        if available + credit_limit < amount + fee {
             error!(
-                "not enough funds for withdrawal, available: {}, requested: {amount}",
-                client_info.available
+                "not enough funds for withdrawal, available: {available}, credit limit: {credit_limit}, requested: {amount}, fee: {fee}"
             );
-            transaction_info.status = TransactionStatus::Failure;
-        } else {
-            client_info.available -= amount;
+            self.transaction_table
+                .set_status(&transaction_id, TransactionStatus::Failure);
+            return Ok(());
         }
-        Ok(())
+        self.client_table.get_or_default(client_id).available -= amount;
+        self.flows.withdrawals += amount;
+        self.client_flows.record(client_id, -amount);
+        self.velocity_tracker.record(client_id, amount, timestamp);
+        self.charge_fee(
+            transaction_id,
+            client_id,
+            TransactionType::Withdrawal,
+            amount,
+        )
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process_dispute(
         &mut self,
         transaction_id: TransactionId,
         client_id: ClientId,
+        timestamp: DateTime<Utc>,
     ) -> anyhow::Result<()> {
         if !self.transaction_table.contains_key(&transaction_id) {
             bail!("dispute failure, transaction not found: {transaction_id}");
@@ -114,18 +1083,30 @@ impl Service {
         if !self.client_table.contains_key(&client_id) {
             bail!("dispute failure, client not found: {client_id}");
         }
+        if self.dispute_table.contains_key(&transaction_id) {
+            bail!("dispute failure, transaction already disputed: {transaction_id}");
+        }
         let transaction_info = self
             .transaction_table
-            .get_mut(&transaction_id)
+            .get(&transaction_id)
             .expect("transaction id should exist");
-        Self::validate_dispute_transaction(client_id, transaction_info)?;
+        Self::validate_dispute_transaction(client_id, &transaction_info)?;
+        let transaction_time = transaction_info.timestamp.context(format!(
+            "dispute failure, transaction time not kept: {transaction_id}"
+        ))?;
+        if !self
+            .dispute_config
+            .is_within_window(transaction_time, timestamp)
+        {
+            bail!(
+                "dispute failure, dispute window expired for transaction from: {transaction_time}"
+            );
+        }
         let client_info = self
             .client_table
             .get_mut(&client_id)
             .context(format!("client id not found: {client_id}"))?;
-        let amount = transaction_info
-            .amount
-            .context("transaction missing 'amount' field")?;
+        let amount = transaction_info.amount;
         if client_info.available < amount {
             bail!(
                 "dispute failure, not enough funds, available: {}, requested: {amount}",
@@ -133,15 +1114,22 @@ impl Service {
             );
         }
         // improvement: execute operations atomically -> create AtomicTransaction class
-        self.dispute_table.insert(transaction_id, client_id);
+        self.dispute_table.insert(
+            transaction_id,
+            DisputeInfo {
+                client: client_id,
+                opened_at: timestamp,
+            },
+        );
         client_info.available -= amount;
         client_info.on_hold += amount;
-        Ok(())
+        self.queue_dispute_expiry(transaction_id, timestamp);
+        self.charge_fee(transaction_id, client_id, TransactionType::Dispute, amount)
     }
 
     fn validate_dispute_transaction(
         client_id: ClientId,
-        transaction_info: &mut TransactionInfo,
+        transaction_info: &TransactionInfo,
     ) -> anyhow::Result<()> {
         if transaction_info.r#type != TransactionType::Deposit {
             bail!(
@@ -164,6 +1152,7 @@ impl Service {
         Ok(())
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process_resolve(
         &mut self,
         transaction_id: TransactionId,
@@ -175,8 +1164,9 @@ impl Service {
         let dispute_client_id = self
             .dispute_table
             .get(&transaction_id)
-            .expect("transaction id should exist");
-        if dispute_client_id != &client_id {
+            .expect("transaction id should exist")
+            .client;
+        if dispute_client_id != client_id {
             // improvement: remove sensitive information from logs
             bail!("resolve failure, client id mismatch: requested client id: {client_id}, existing client id: {dispute_client_id}");
         }
@@ -188,21 +1178,28 @@ impl Service {
             .transaction_table
             .get(&transaction_id)
             .expect("transaction id should exist");
-        assert_ne!(transaction_info.status, TransactionStatus::Failure);
-        let amount = transaction_info
-            .amount
-            .context("transaction missing 'amount' field")?;
+        if transaction_info.status == TransactionStatus::Failure {
+            bail!(
+                "resolve failure, incorrect transaction state: {}",
+                transaction_info.status
+            );
+        }
+        let amount = transaction_info.amount;
+        client_info
+            .release_held(amount)
+            .context("resolve failure")?;
         self.dispute_table.remove(&transaction_id);
         self.chargeback_table.insert(transaction_id);
         // improvement: resolve is a mirror operation to holding money and should be done in one place.
         // Possible solution: implement ReversableAction class where on "exec" you hold the money
         // and on "reverse" you do the opposite
         client_info.available += amount;
-        assert!(client_info.on_hold >= amount);
-        client_info.on_hold -= amount;
-        Ok(())
+        let origin_type = transaction_info.r#type;
+        self.reverse_fees(transaction_id, origin_type)?;
+        self.charge_fee(transaction_id, client_id, TransactionType::Resolve, amount)
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process_chargeback(
         &mut self,
         transaction_id: TransactionId,
@@ -224,9 +1221,7 @@ impl Service {
             .client_table
             .get_mut(&client_id)
             .context(format!("client id not found: {client_id}"))?;
-        let amount = transaction_info
-            .amount
-            .context("transaction missing 'amount' field")?;
+        let amount = transaction_info.amount;
         if client_info.available < amount {
             bail!(
                 "not enough funds for chargeback, available: {}, requested: {amount}",
@@ -237,31 +1232,428 @@ impl Service {
         self.chargeback_table.remove(&transaction_id);
         client_info.available -= amount;
         client_info.is_locked = true;
-        Ok(())
-    }
-}
-
-pub trait TransactionRecordHandler {
-    async fn handle(&mut self, record: &InputFileRecord) -> anyhow::Result<()>;
-}
-
-impl TransactionRecordHandler for Service {
-    async fn handle(&mut self, record: &InputFileRecord) -> anyhow::Result<()> {
-        debug!("handle_transaction: {record:?}");
-        let &InputFileRecord {
-            r#type,
-            client,
-            tx,
+        self.flows.chargebacks += amount;
+        self.client_flows.record(client_id, -amount);
+        self.charge_fee(
+            transaction_id,
+            client_id,
+            TransactionType::Chargeback,
             amount,
-        } = record;
+        )
+    }
 
-        let client_id = ClientId::new(client as u16);
-        if self.client_table.contains_key(&client_id) {
-            let client_info = self
-                .client_table
-                .get(&client_id)
-                .context(format!("client id not found: {client_id}"))?;
-            if r#type != InputFileRecordType::Resolve && client_info.is_locked {
+    /// Undoes a deposit or withdrawal, releasing its dispute hold if there is one
+    #[instrument(level = "debug", skip_all)]
+    fn process_reversal(
+        &mut self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        reason: Option<&str>,
+        timestamp: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let reason = reason.context("reversal transaction missing 'reason' field")?;
+        let transaction_info = self
+            .transaction_table
+            .get(&transaction_id)
+            .context(format!(
+                "reversal failure, transaction not found: {transaction_id}"
+            ))?;
+        if transaction_info.client != client_id {
+            // improvement: remove sensitive information from logs
+            bail!("reversal failure, client id mismatch: requested client id: {client_id}, existing client id: {}", transaction_info.client);
+        }
+        if transaction_info.status != TransactionStatus::Success {
+            bail!(
+                "reversal failure, incorrect transaction state: {}",
+                transaction_info.status
+            );
+        }
+        let amount = transaction_info.amount;
+        let origin_type = transaction_info.r#type;
+        let credit_limit = self.credit_limit(client_id);
+        let refunded_fees = self.revenue_account.posted(transaction_id, origin_type);
+        let client_info = self
+            .client_table
+            .get_mut(&client_id)
+            .context(format!("client id not found: {client_id}"))?;
+        match origin_type {
+            TransactionType::Deposit => {
+                if self.dispute_table.contains_key(&transaction_id) {
+                    client_info
+                        .release_held(amount)
+                        .context("reversal failure")?;
+                    self.dispute_table.remove(&transaction_id);
+                } else {
+                    // a deposit already spent may only be taken back down to the credit limit
+                    if client_info.available + refunded_fees + credit_limit < amount {
+                        bail!(
+                            "reversal failure, not enough funds, available: {}, credit limit: {credit_limit}, requested: {amount}",
+                            client_info.available
+                        );
+                    }
+                    self.chargeback_table.remove(&transaction_id);
+                    client_info.available -= amount;
+                }
+                self.flows.deposits -= amount;
+                self.client_flows.record(client_id, -amount);
+            }
+            TransactionType::Withdrawal => {
+                client_info.available += amount;
+                self.flows.withdrawals -= amount;
+                self.client_flows.record(client_id, amount);
+            }
+            _ => bail!("reversal failure, incorrect transaction type: {origin_type}"),
+        }
+        self.transaction_table
+            .set_status(&transaction_id, TransactionStatus::Reversed);
+        self.reversal_table.insert(
+            transaction_id,
+            ReversalInfo {
+                reason: reason.to_string(),
+                reversed_at: timestamp,
+            },
+        );
+        self.reverse_fees(transaction_id, origin_type)
+    }
+
+    /// Reserves funds by moving them from available to held
+    #[instrument(level = "debug", skip_all)]
+    fn process_authorize(
+        &mut self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        amount: Option<f64>,
+        timestamp: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let amount = amount.context("authorize transaction missing 'amount' field")?;
+        // never stored, a snapshot could not hold it
+        if !amount.is_finite() {
+            bail!("cannot authorize amount out of range: {amount}");
+        }
+        if self.is_replay(
+            transaction_id,
+            client_id,
+            TransactionType::Authorize,
+            amount,
+        )? {
+            return Ok(());
+        }
+        let credit_limit = self.credit_limit(client_id);
+        self.transaction_table.insert(
+            transaction_id,
+            TransactionInfo {
+                r#type: TransactionType::Authorize,
+                client: client_id,
+                amount,
+                status: TransactionStatus::Success,
+                timestamp: None,
+            },
+        );
+        if amount > MAX_AMOUNT {
+            self.fail_transaction(transaction_id);
+            bail!("cannot authorize amount out of range: {amount}");
+        }
+        if amount < 0f64 {
+            self.fail_transaction(transaction_id);
+            bail!("cannot authorize negative amount: {amount}");
+        }
+        let Some(client_info) = self.client_table.get_mut(&client_id) else {
+            self.transaction_table
+                .set_status(&transaction_id, TransactionStatus::Failure);
+            bail!("client id not found: {client_id}");
+        };
+        if client_info.available + credit_limit < amount {
+            self.transaction_table
+                .set_status(&transaction_id, TransactionStatus::Failure);
+            bail!(
+                "authorization failure, not enough funds, available: {}, credit limit: {credit_limit}, requested: {amount}",
+                client_info.available
+            );
+        }
+        client_info.available -= amount;
+        client_info.on_hold += amount;
+        client_info.authorized += amount;
+        self.client_flows.moved(client_id, amount);
+        self.authorization_table.insert(
+            transaction_id,
+            AuthorizationInfo {
+                client: client_id,
+                amount,
+                authorized_at: timestamp,
+            },
+        );
+        self.queue_authorization_expiry(transaction_id, timestamp);
+        Ok(())
+    }
+
+    fn open_authorization(
+        &self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        operation: &str,
+    ) -> anyhow::Result<AuthorizationInfo> {
+        let authorization = self
+            .authorization_table
+            .get(&transaction_id)
+            .context(format!(
+                "{operation} failure, open authorization not found: {transaction_id}"
+            ))?;
+        if authorization.client != client_id {
+            // improvement: remove sensitive information from logs
+            bail!("{operation} failure, client id mismatch: requested client id: {client_id}, existing client id: {}", authorization.client);
+        }
+        Ok(*authorization)
+    }
+
+    /// Captures `amount`, or the whole authorization when not given, and releases the remainder
+    #[instrument(level = "debug", skip_all)]
+    fn process_capture(
+        &mut self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        amount: Option<f64>,
+    ) -> anyhow::Result<()> {
+        let authorization = self.open_authorization(transaction_id, client_id, "capture")?;
+        let captured = amount.unwrap_or(authorization.amount);
+        if captured.is_nan() || captured < 0f64 || captured > authorization.amount {
+            bail!(
+                "capture failure, invalid amount: {captured}, authorized: {}",
+                authorization.amount
+            );
+        }
+        self.release_authorization(transaction_id, TransactionStatus::Captured)?;
+        let client_info = self
+            .client_table
+            .get_mut(&client_id)
+            .context(format!("client id not found: {client_id}"))?;
+        client_info.available -= captured;
+        self.flows.captures += captured;
+        self.client_flows.record(client_id, -captured);
+        self.charge_fee(
+            transaction_id,
+            client_id,
+            TransactionType::Capture,
+            captured,
+        )
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn process_void(
+        &mut self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+    ) -> anyhow::Result<()> {
+        self.open_authorization(transaction_id, client_id, "void")?;
+        self.release_authorization(transaction_id, TransactionStatus::Voided)
+    }
+
+    /// Moves the funds of an open authorization back to available and closes it with `status`
+    fn release_authorization(
+        &mut self,
+        transaction_id: TransactionId,
+        status: TransactionStatus,
+    ) -> anyhow::Result<()> {
+        let Some(authorization) = self.authorization_table.get(&transaction_id) else {
+            return Ok(());
+        };
+        let amount = authorization.amount;
+        if let Some(client_info) = self.client_table.get_mut(&authorization.client) {
+            client_info
+                .release_held(amount)
+                .context("authorization release failure")?;
+            client_info.authorized -= amount;
+            client_info.available += amount;
+        }
+        self.authorization_table.remove(&transaction_id);
+        self.transaction_table.set_status(&transaction_id, status);
+        Ok(())
+    }
+}
+
+// only used by this crate and its fuzz targets, no `Send` bound needed on the future
+#[allow(async_fn_in_trait)]
+pub trait TransactionRecordHandler {
+    async fn handle(&mut self, record: &InputFileRecord) -> anyhow::Result<()>;
+}
+
+impl TransactionRecordHandler for Service {
+    async fn handle(&mut self, record: &InputFileRecord) -> anyhow::Result<()> {
+        let span = record.span();
+        let _entered = span.enter();
+        if self.metrics.is_none() && self.audit_log.is_none() {
+            return self.handle_record(record);
+        }
+        let started = Instant::now();
+        let outcome = self.handle_record(record);
+        let rejection = self.rejection(record, &outcome);
+        if let Some(metrics) = &self.metrics {
+            metrics.record(record.r#type, rejection.as_deref(), started.elapsed());
+            if self
+                .run_summary
+                .records
+                .is_multiple_of(METRICS_REFRESH_INTERVAL)
+            {
+                self.refresh_metrics();
+            }
+        }
+        self.audit(|| AuditEvent::record(record, rejection.as_deref()));
+        outcome
+    }
+}
+
+impl Service {
+    fn handle_record(&mut self, record: &InputFileRecord) -> anyhow::Result<()> {
+        debug!(
+            amount = record.amount,
+            timestamp = record.timestamp.map(|timestamp| timestamp.to_rfc3339()),
+            reason = record.reason,
+            "handling transaction"
+        );
+        self.run_summary.records += 1;
+        let client_id = ClientId::new(record.client as u16);
+        let hits = self
+            .rule_engine
+            .before(record, self.client_table.get(&client_id));
+        if let Some(rejection) = self.enforce_rules(record, hits) {
+            self.run_summary.rejected += 1;
+            return Err(rejection.into());
+        }
+        let outcome = self.apply(record);
+        let mut hits = self
+            .rule_engine
+            .after(record, self.client_table.get(&client_id), &outcome);
+        if let Err(err) = &outcome {
+            self.run_summary.rejected += 1;
+            if err.is::<TransactionConflict>() {
+                self.run_summary.conflicts += 1;
+                // the record already failed, the hit only lists it in the flagged report
+                hits.push(RuleHit {
+                    rule: "idempotency".to_string(),
+                    action: RuleAction::Flag,
+                    reason: err.to_string(),
+                });
+            }
+        }
+        self.enforce_rules(record, hits);
+        if self.invariant_config.check == InvariantCheck::PerTransaction {
+            self.check_invariants(&format!("tx {}", record.tx));
+        }
+        outcome
+    }
+}
+
+impl Service {
+    /// Type of the transaction a record of this type stores
+    fn stored_type(r#type: InputFileRecordType) -> Option<TransactionType> {
+        match r#type {
+            InputFileRecordType::Deposit => Some(TransactionType::Deposit),
+            InputFileRecordType::Withdrawal => Some(TransactionType::Withdrawal),
+            InputFileRecordType::Authorize => Some(TransactionType::Authorize),
+            _ => None,
+        }
+    }
+
+    /// Error message of a handled record that was rejected, either with an error or by storing
+    /// its transaction as failed, such as a withdrawal without enough funds
+    pub fn rejection(
+        &self,
+        record: &InputFileRecord,
+        outcome: &anyhow::Result<()>,
+    ) -> Option<String> {
+        if let Err(err) = outcome {
+            return Some(err.to_string());
+        }
+        let stored_type = Self::stored_type(record.r#type)?;
+        let transaction_id = TransactionId::new(record.tx);
+        self.transaction_table
+            .get(&transaction_id)
+            .filter(|info| {
+                info.r#type == stored_type
+                    && info.client.value() as u64 == record.client
+                    && info.status == TransactionStatus::Failure
+            })
+            .map(|_| format!("transaction stored as failed: {transaction_id}"))
+    }
+
+    /// Like `handle`, but records that are ignored or stored as failed are errors as well
+    pub async fn handle_strict(&mut self, record: &InputFileRecord) -> anyhow::Result<()> {
+        if record.r#type != InputFileRecordType::Resolve && self.is_locked(record.client) {
+            bail!("client is locked: {}", record.client);
+        }
+        self.handle(record).await?;
+        let Some(stored_type) = Self::stored_type(record.r#type) else {
+            return Ok(());
+        };
+        let transaction_id = TransactionId::new(record.tx);
+        match self.transaction_table.get(&transaction_id) {
+            Some(info)
+                if info.r#type == stored_type && info.status == TransactionStatus::Success =>
+            {
+                Ok(())
+            }
+            Some(info) => bail!(
+                "transaction not applied: {transaction_id}, status: {}",
+                info.status
+            ),
+            None => bail!("transaction not applied: {transaction_id}"),
+        }
+    }
+
+    /// Records rule hits and locks clients where requested, returns the first rejection
+    fn enforce_rules(
+        &mut self,
+        record: &InputFileRecord,
+        hits: Vec<RuleHit>,
+    ) -> Option<RuleRejection> {
+        let mut rejection = None;
+        for hit in hits {
+            warn!(
+                "rule {} hit, action: {}, transaction: {}, reason: {}",
+                hit.rule, hit.action, record.tx, hit.reason
+            );
+            if hit.action == RuleAction::Lock {
+                // a rejected record must not open an account, so only existing clients are locked
+                let client_id = ClientId::new(record.client as u16);
+                if let Some(client_info) = self.client_table.get_mut(&client_id) {
+                    client_info.is_locked = true;
+                }
+            }
+            if hit.action != RuleAction::Flag && rejection.is_none() {
+                rejection = Some(RuleRejection {
+                    rule: hit.rule.clone(),
+                    reason: hit.reason.clone(),
+                });
+            }
+            self.flagged_transactions.push(FlaggedTransaction {
+                r#type: record.r#type,
+                client: record.client,
+                tx: record.tx,
+                hit,
+            });
+        }
+        rejection
+    }
+
+    fn apply(&mut self, record: &InputFileRecord) -> anyhow::Result<()> {
+        let &InputFileRecord {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp,
+            ref reason,
+        } = record;
+        let timestamp = timestamp.unwrap_or_else(|| self.clock.now());
+        self.expire_disputes(timestamp);
+        self.expire_authorizations(timestamp);
+
+        let client_id = ClientId::new(client as u16);
+        if self.client_table.contains_key(&client_id) {
+            let client_info = self
+                .client_table
+                .get(&client_id)
+                .context(format!("client id not found: {client_id}"))?;
+            if r#type != InputFileRecordType::Resolve && client_info.is_locked {
                 warn!("ignore transaction for locked client: {client_id}");
                 return Ok(());
             }
@@ -269,24 +1661,30 @@ impl TransactionRecordHandler for Service {
 
         let transaction_id = TransactionId::new(tx);
 
-        match r#type {
+        let available = self.available(client_id);
+        let outcome = match r#type {
             InputFileRecordType::Deposit => {
-                self.process_deposit(transaction_id, client_id, amount)?;
+                self.process_deposit(transaction_id, client_id, amount, timestamp)
             }
             InputFileRecordType::Withdrawal => {
-                self.process_withdrawal(transaction_id, client_id, amount)?;
+                self.process_withdrawal(transaction_id, client_id, amount, timestamp)
             }
             InputFileRecordType::Dispute => {
-                self.process_dispute(transaction_id, client_id)?;
+                self.process_dispute(transaction_id, client_id, timestamp)
             }
-            InputFileRecordType::Resolve => {
-                self.process_resolve(transaction_id, client_id)?;
+            InputFileRecordType::Resolve => self.process_resolve(transaction_id, client_id),
+            InputFileRecordType::Chargeback => self.process_chargeback(transaction_id, client_id),
+            InputFileRecordType::Reversal => {
+                self.process_reversal(transaction_id, client_id, reason.as_deref(), timestamp)
             }
-            InputFileRecordType::Chargeback => {
-                self.process_chargeback(transaction_id, client_id)?;
+            InputFileRecordType::Authorize => {
+                self.process_authorize(transaction_id, client_id, amount, timestamp)
             }
-        }
-        Ok(())
+            InputFileRecordType::Capture => self.process_capture(transaction_id, client_id, amount),
+            InputFileRecordType::Void => self.process_void(transaction_id, client_id),
+        };
+        self.record_balance_change(client_id, available, timestamp);
+        outcome
     }
 }
 
@@ -298,10 +1696,88 @@ impl OutputRecordProvider for Service {
                 client: client_id.value() as u64,
                 available: info.available,
                 held: info.on_hold,
-                total: info.on_hold + info.available,
+                total: info.total(),
                 locked: info.is_locked,
             })
     }
+
+    fn get_fee_records(&self) -> impl Iterator<Item = FeeRecord> {
+        self.revenue_account.postings().map(|posting| FeeRecord {
+            id: posting.id.value(),
+            origin: posting.origin.value(),
+            charged_for: posting.charged_for,
+            client: posting.client.value() as u64,
+            amount: posting.amount,
+            status: posting.status,
+        })
+    }
+
+    fn get_history_records(&self) -> impl Iterator<Item = HistoryRecord> {
+        // only deposits that can still be disputed keep their time, so the history is in id order
+        let mut transactions = self.transaction_table.iter().collect::<Vec<_>>();
+        transactions.sort_by_key(|(transaction_id, _)| *transaction_id);
+        transactions.into_iter().map(|(transaction_id, info)| {
+            let reversal = self.reversal_table.get(&transaction_id);
+            HistoryRecord {
+                tx: transaction_id.value(),
+                r#type: info.r#type,
+                client: info.client.value() as u64,
+                amount: Some(info.amount),
+                status: info.status,
+                timestamp: info.timestamp,
+                reversed_at: reversal.map(|reversal| reversal.reversed_at),
+                reason: reversal.map(|reversal| reversal.reason.clone()),
+            }
+        })
+    }
+
+    fn get_scheduled_records(&self) -> impl Iterator<Item = ScheduledRecord> {
+        self.scheduled_records.iter().cloned()
+    }
+
+    fn get_statement_records(&self) -> impl Iterator<Item = StatementRecord> {
+        self.client_table.iter().map(|(client_id, info)| {
+            let credit_limit = self.credit_limit(client_id);
+            StatementRecord {
+                client: client_id.value() as u64,
+                available: info.available,
+                held: info.on_hold,
+                held_disputed: info.on_hold - info.authorized,
+                held_authorized: info.authorized,
+                total: info.total(),
+                credit_limit,
+                overdraft_used: (-info.available).max(0f64),
+                locked: info.is_locked,
+            }
+        })
+    }
+
+    fn get_flagged_records(&self) -> impl Iterator<Item = FlaggedRecord> {
+        self.flagged_transactions
+            .iter()
+            .map(|flagged| FlaggedRecord {
+                r#type: flagged.r#type,
+                client: flagged.client,
+                tx: flagged.tx,
+                rule: flagged.hit.rule.clone(),
+                action: flagged.hit.action,
+                reason: flagged.hit.reason.clone(),
+            })
+    }
+
+    fn get_interest_records(&self) -> impl Iterator<Item = InterestRecord> {
+        self.interest_ledger.postings().flat_map(|posting| {
+            posting.accruals.iter().map(|accrual| InterestRecord {
+                id: posting.id.value(),
+                client: posting.client.value() as u64,
+                date: accrual.date,
+                balance: accrual.balance,
+                annual_rate: accrual.annual_rate,
+                day_count_basis: accrual.day_count_basis,
+                amount: accrual.amount,
+            })
+        })
+    }
 }
 
 // improvement: add more tests:
@@ -310,20 +1786,45 @@ impl OutputRecordProvider for Service {
 // - reading input file/writing output file
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::{
+        client_config::ClientConfig,
+        client_id::ClientId,
+        clock::test_clock::ManualClock,
+        config::Config,
+        fraud_rules::RuleAction,
         input_file_reader::{InputFileRecord, InputFileRecordType},
+        limits::{LimitViolation, WithdrawalLimits},
         output_record::OutputRecord,
-        *,
+        run_summary::RunSummary,
+        scheduler::{AttemptOutcome, Recurrence, ScheduledInstruction},
+        transaction_info::TransactionStatus,
     };
+    use chrono::{DateTime, NaiveDate, Utc};
     use std::sync::Once;
 
     static INIT: Once = Once::new();
 
     fn setup() -> Service {
-        INIT.call_once(env_logger::init);
+        INIT.call_once(crate::logging::init);
         Service::new()
     }
 
+    fn setup_with_config(config: &str) -> Service {
+        INIT.call_once(crate::logging::init);
+        Service::with_config(toml::from_str(config).expect("config should parse"))
+    }
+
+    fn assert_client(service: &Service, client: u64, available: f64, held: f64, locked: bool) {
+        let record = service
+            .get_records()
+            .find(|record| record.client == client)
+            .expect("client should exist");
+        assert!((record.available - available).abs() < 1e-9, "{record:?}");
+        assert!((record.held - held).abs() < 1e-9, "{record:?}");
+        assert_eq!(record.locked, locked);
+    }
+
     #[tokio::test]
     async fn deposit_and_withdrawal() {
         let mut service = setup();
@@ -333,6 +1834,8 @@ mod tests {
                 client: 1,
                 tx: 0,
                 amount: Some(10.0),
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle deposit request");
@@ -357,6 +1860,8 @@ mod tests {
                 client: 1,
                 tx: 1,
                 amount: Some(1.5),
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle withdrawal request");
@@ -386,6 +1891,8 @@ mod tests {
                 client: 1,
                 tx: 0,
                 amount: Some(10.0),
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle deposit request");
@@ -410,6 +1917,8 @@ mod tests {
                 client: 1,
                 tx: 0,
                 amount: None,
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle dispute request");
@@ -435,6 +1944,8 @@ mod tests {
                 client: 1,
                 tx: 0,
                 amount: None,
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle resolution request");
@@ -460,6 +1971,8 @@ mod tests {
                 client: 1,
                 tx: 0,
                 amount: None,
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle chargeback request");
@@ -489,6 +2002,8 @@ mod tests {
                 client: 2,
                 tx: 3,
                 amount: Some(10.0),
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle deposit request");
@@ -513,6 +2028,8 @@ mod tests {
                 client: 2,
                 tx: 0,
                 amount: Some(11.0),
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle withdrawal request");
@@ -542,6 +2059,8 @@ mod tests {
                 client: 1,
                 tx: 0,
                 amount: Some(-10.0),
+                timestamp: None,
+                reason: None,
             })
             .await;
         assert!(res.is_err());
@@ -551,6 +2070,33 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn deposit_out_of_range() {
+        let mut service = setup();
+        // partner files may spell these out, the CSV reader parses them as floats
+        for (tx, amount) in [(0, f64::NAN), (1, f64::INFINITY), (2, 1e12)] {
+            let res = service
+                .handle(&InputFileRecord {
+                    r#type: InputFileRecordType::Deposit,
+                    client: 1,
+                    tx,
+                    amount: Some(amount),
+                    timestamp: None,
+                    reason: None,
+                })
+                .await;
+            assert_eq!(
+                res.err().unwrap().to_string(),
+                format!("cannot deposit amount out of range: {amount}")
+            );
+        }
+        assert_eq!(service.get_records().count(), 0);
+        // only the finite amount is stored, as a failed transaction
+        let snapshot = service.export_snapshot();
+        assert_eq!(snapshot.transactions.len(), 1);
+        assert_eq!(snapshot.transactions[0].amount, 1e12);
+    }
+
     #[tokio::test]
     async fn negative_withdrawal() {
         let mut service = setup();
@@ -560,6 +2106,8 @@ mod tests {
                 client: 1,
                 tx: 0,
                 amount: Some(10.0),
+                timestamp: None,
+                reason: None,
             })
             .await
             .expect("service failed to handle deposit request");
@@ -584,6 +2132,8 @@ mod tests {
                 client: 1,
                 tx: 1,
                 amount: Some(-1.5),
+                timestamp: None,
+                reason: None,
             })
             .await;
         assert!(res.is_err());
@@ -592,4 +2142,1171 @@ mod tests {
             "cannot withdraw negative amount: -1.5"
         );
     }
+
+    #[tokio::test]
+    async fn fees_are_charged_and_reversed() {
+        let mut service = setup_with_config(
+            r#"
+            [fees.deposit]
+            policy = "flat"
+            amount = 1.0
+
+            [fees.withdrawal]
+            policy = "percentage"
+            rate = 0.1
+
+            [fees.chargeback]
+            policy = "flat"
+            amount = 1.0
+            "#,
+        );
+        let records = [
+            (InputFileRecordType::Deposit, 1, Some(10.0)),
+            (InputFileRecordType::Deposit, 2, Some(5.0)),
+            (InputFileRecordType::Withdrawal, 3, Some(2.0)),
+        ];
+        for (r#type, tx, amount) in records {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client: 1,
+                    tx,
+                    amount,
+                    timestamp: None,
+                    reason: None,
+                })
+                .await
+                .expect("service failed to handle request");
+        }
+        assert_client(&service, 1, 10.8, 0.0, false);
+        assert!((service.revenue_account().balance() - 2.2).abs() < 1e-9);
+
+        for r#type in [InputFileRecordType::Dispute, InputFileRecordType::Resolve] {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client: 1,
+                    tx: 1,
+                    amount: None,
+                    timestamp: None,
+                    reason: None,
+                })
+                .await
+                .expect("service failed to handle request");
+        }
+        // deposit fee of the disputed transaction is refunded
+        assert_client(&service, 1, 11.8, 0.0, false);
+        assert!((service.revenue_account().balance() - 1.2).abs() < 1e-9);
+
+        service
+            .handle(&InputFileRecord {
+                r#type: InputFileRecordType::Chargeback,
+                client: 1,
+                tx: 1,
+                amount: None,
+                timestamp: None,
+                reason: None,
+            })
+            .await
+            .expect("service failed to handle chargeback request");
+        assert_client(&service, 1, 0.8, 0.0, true);
+        assert!((service.revenue_account().balance() - 2.2).abs() < 1e-9);
+        let fee_records = service.get_fee_records().collect::<Vec<_>>();
+        assert_eq!(fee_records.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn withdrawal_fee_exceeds_funds() {
+        let mut service = setup_with_config(
+            r#"
+            [fees.withdrawal]
+            policy = "flat"
+            amount = 0.5
+            "#,
+        );
+        for (r#type, tx, amount) in [
+            (InputFileRecordType::Deposit, 1, 10.0),
+            (InputFileRecordType::Withdrawal, 2, 10.0),
+        ] {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client: 1,
+                    tx,
+                    amount: Some(amount),
+                    timestamp: None,
+                    reason: None,
+                })
+                .await
+                .expect("service failed to handle request");
+        }
+        assert_client(&service, 1, 10.0, 0.0, false);
+        assert_eq!(service.revenue_account().balance(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn interest_accrual() {
+        let mut service = setup_with_config(
+            r#"
+            [interest]
+            day_count_basis = 360
+            rates = [{ effective_from = "2024-01-01", tiers = [{ min_balance = 0.0, annual_rate = 0.036 }] }]
+            "#,
+        );
+        for (r#type, client, tx, amount) in [
+            (InputFileRecordType::Deposit, 1, 1, Some(1000.0)),
+            (InputFileRecordType::Deposit, 2, 2, Some(1000.0)),
+            (InputFileRecordType::Dispute, 2, 2, None),
+            (InputFileRecordType::Resolve, 2, 2, None),
+            (InputFileRecordType::Chargeback, 2, 2, None),
+        ] {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client,
+                    tx,
+                    amount,
+                    timestamp: Some("2024-04-30T12:00:00Z".parse().unwrap()),
+                    reason: None,
+                })
+                .await
+                .expect("service failed to handle request");
+        }
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+
+        assert_eq!(service.accrue_interest(date("2024-05-01")).unwrap(), 1);
+        assert_client(&service, 1, 1000.1, 0.0, false);
+        assert_client(&service, 2, 0.0, 0.0, true);
+
+        let res = service.accrue_interest(date("2024-05-01"));
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "interest already accrued up to 2024-05-01"
+        );
+
+        // catches up on every day since the previous accrual
+        assert_eq!(service.accrue_interest(date("2024-05-03")).unwrap(), 1);
+        assert_client(&service, 1, 1000.30002, 0.0, false);
+        let basis = service.get_interest_records().collect::<Vec<_>>();
+        assert_eq!(basis.len(), 3);
+        assert_eq!(basis[2].date, date("2024-05-03"));
+        assert_eq!(basis[2].balance, basis[1].balance);
+    }
+
+    #[tokio::test]
+    async fn interest_accrues_on_end_of_day_balances() {
+        let mut service = setup_with_config(
+            r#"
+            [interest]
+            day_count_basis = 360
+            rates = [{ effective_from = "2024-01-01", tiers = [{ min_balance = 0.0, annual_rate = 0.036 }] }]
+            "#,
+        );
+        let record = |r#type, tx, amount, timestamp: &str| InputFileRecord {
+            r#type,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            timestamp: Some(timestamp.parse().unwrap()),
+            reason: None,
+        };
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+        service
+            .handle(&record(
+                InputFileRecordType::Deposit,
+                1,
+                1000.0,
+                "2024-05-01T09:00:00Z",
+            ))
+            .await
+            .expect("service failed to handle request");
+        assert_eq!(service.accrue_interest(date("2024-05-01")).unwrap(), 1);
+
+        for record in [
+            record(
+                InputFileRecordType::Deposit,
+                2,
+                1000.0,
+                "2024-05-03T10:00:00Z",
+            ),
+            // after the accrual date, not part of any accrued day yet
+            record(
+                InputFileRecordType::Withdrawal,
+                3,
+                500.0,
+                "2024-05-05T10:00:00Z",
+            ),
+        ] {
+            service
+                .handle(&record)
+                .await
+                .expect("service failed to handle request");
+        }
+        assert_eq!(service.accrue_interest(date("2024-05-04")).unwrap(), 1);
+        let balances = service
+            .get_interest_records()
+            .map(|record| (record.date, record.balance))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            balances,
+            vec![
+                (date("2024-05-01"), 1000.0),
+                (date("2024-05-02"), 1000.1),
+                (date("2024-05-03"), 2000.1),
+                (date("2024-05-04"), 2000.1),
+            ]
+        );
+        assert_client(&service, 1, 1500.60003, 0.0, false);
+    }
+
+    #[tokio::test]
+    async fn restore_rolls_back_rule_state() {
+        let mut service = setup_with_config(
+            r#"
+            [[rules]]
+            rule = "failed_disputes"
+            max_failures = 2
+            action = "lock"
+            "#,
+        );
+        let record = |r#type, tx, amount| InputFileRecord {
+            r#type,
+            client: 1,
+            tx,
+            amount,
+            timestamp: None,
+            reason: None,
+        };
+        service
+            .handle(&record(InputFileRecordType::Deposit, 1, Some(10.0)))
+            .await
+            .expect("service failed to handle request");
+        let checkpoint = service.checkpoint();
+        let dispute = record(InputFileRecordType::Dispute, 7, None);
+        assert!(service.handle(&dispute).await.is_err());
+        service.restore(checkpoint);
+        // the failed dispute before the restore no longer counts
+        assert!(service.handle(&dispute).await.is_err());
+        assert_client(&service, 1, 10.0, 0.0, false);
+        assert!(service.handle(&dispute).await.is_err());
+        assert_client(&service, 1, 10.0, 0.0, true);
+    }
+
+    #[tokio::test]
+    async fn withdrawal_within_credit_limit() {
+        INIT.call_once(crate::logging::init);
+        let mut config = Config::default();
+        config.clients.insert(
+            ClientId::new(1),
+            ClientConfig {
+                credit_limit: 20.0,
+                ..Default::default()
+            },
+        );
+        let mut service = Service::with_config(config);
+        for (tx, r#type, amount) in [
+            (1, InputFileRecordType::Deposit, 10.0),
+            (2, InputFileRecordType::Withdrawal, 25.0),
+            (3, InputFileRecordType::Withdrawal, 10.0),
+        ] {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client: 1,
+                    tx,
+                    amount: Some(amount),
+                    timestamp: None,
+                    reason: None,
+                })
+                .await
+                .expect("service failed to handle request");
+        }
+        assert_client(&service, 1, -15.0, 0.0, false);
+        let statement = service.get_statement_records().collect::<Vec<_>>();
+        assert_eq!(statement.len(), 1);
+        assert_eq!(statement[0].credit_limit, 20.0);
+        assert_eq!(statement[0].overdraft_used, 15.0);
+    }
+
+    #[tokio::test]
+    async fn overdraft_as_the_first_transaction() {
+        INIT.call_once(crate::logging::init);
+        let mut config = Config::default();
+        config.clients.insert(
+            ClientId::new(1),
+            ClientConfig {
+                credit_limit: 20.0,
+                ..Default::default()
+            },
+        );
+        let mut service = Service::with_config(config);
+        let mut withdraw = async |client, tx| {
+            service
+                .handle(&InputFileRecord {
+                    r#type: InputFileRecordType::Withdrawal,
+                    client,
+                    tx,
+                    amount: Some(5.0),
+                    timestamp: None,
+                    reason: None,
+                })
+                .await
+        };
+        withdraw(1, 1)
+            .await
+            .expect("service failed to handle request");
+        assert_eq!(
+            withdraw(2, 2).await.unwrap_err().to_string(),
+            "client id not found: 2"
+        );
+        assert_client(&service, 1, -5.0, 0.0, false);
+    }
+
+    #[tokio::test]
+    async fn failed_overdraft_opens_no_account() {
+        INIT.call_once(crate::logging::init);
+        let mut config = Config::default();
+        config.clients.insert(
+            ClientId::new(7),
+            ClientConfig {
+                credit_limit: 20.0,
+                ..Default::default()
+            },
+        );
+        let mut service = Service::with_config(config);
+        service
+            .handle(&InputFileRecord {
+                r#type: InputFileRecordType::Withdrawal,
+                client: 7,
+                tx: 1,
+                amount: Some(50.0),
+                timestamp: None,
+                reason: None,
+            })
+            .await
+            .expect("service failed to handle request");
+        assert_eq!(service.get_records().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn withdrawal_limits() {
+        INIT.call_once(crate::logging::init);
+        let mut config: Config = toml::from_str(
+            r#"
+            [limits]
+            max_withdrawals = 2
+            max_withdrawal_total = 50.0
+            max_single_withdrawal = 30.0
+            "#,
+        )
+        .expect("config should parse");
+        config.clients.insert(
+            ClientId::new(2),
+            ClientConfig {
+                limits: WithdrawalLimits {
+                    max_withdrawals: Some(3),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let mut service = Service::with_config(config);
+        let mut handle = async |client, tx, r#type, amount| {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client,
+                    tx,
+                    amount: Some(amount),
+                    timestamp: None,
+                    reason: None,
+                })
+                .await
+        };
+        handle(1, 1, InputFileRecordType::Deposit, 100.0)
+            .await
+            .unwrap();
+        handle(2, 2, InputFileRecordType::Deposit, 100.0)
+            .await
+            .unwrap();
+
+        let violation = |res: anyhow::Result<()>| {
+            *res.expect_err("withdrawal should be rejected")
+                .downcast_ref::<LimitViolation>()
+                .expect("error should be a limit violation")
+        };
+        assert_eq!(
+            violation(handle(1, 3, InputFileRecordType::Withdrawal, 40.0).await),
+            LimitViolation::SingleWithdrawal {
+                limit: 30.0,
+                amount: 40.0
+            }
+        );
+        handle(1, 4, InputFileRecordType::Withdrawal, 30.0)
+            .await
+            .unwrap();
+        assert!(matches!(
+            violation(handle(1, 5, InputFileRecordType::Withdrawal, 25.0).await),
+            LimitViolation::WithdrawalTotal {
+                withdrawn: 30.0,
+                ..
+            }
+        ));
+        handle(1, 6, InputFileRecordType::Withdrawal, 20.0)
+            .await
+            .unwrap();
+        assert!(matches!(
+            violation(handle(1, 7, InputFileRecordType::Withdrawal, 1.0).await),
+            LimitViolation::WithdrawalCount { limit: 2, .. }
+        ));
+
+        // client override raises the count limit, global total limit still applies
+        for tx in 8..11 {
+            handle(2, tx, InputFileRecordType::Withdrawal, 10.0)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            violation(handle(2, 11, InputFileRecordType::Withdrawal, 10.0).await),
+            LimitViolation::WithdrawalCount { limit: 3, .. }
+        ));
+        assert_client(&service, 1, 50.0, 0.0, false);
+        assert_client(&service, 2, 70.0, 0.0, false);
+    }
+
+    #[tokio::test]
+    async fn fraud_rules() {
+        let mut service = setup_with_config(
+            r#"
+            [[rules]]
+            rule = "large_amount"
+            types = ["deposit"]
+            threshold = 10000.0
+
+            [[rules]]
+            rule = "large_amount"
+            types = ["withdrawal"]
+            threshold = 10000.0
+            action = "lock"
+
+            [[rules]]
+            rule = "failed_disputes"
+            max_failures = 3
+            action = "lock"
+
+            [[rules]]
+            rule = "deny_clients"
+            clients = [3]
+            "#,
+        );
+        let mut handle = async |r#type, client, tx, amount| {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client,
+                    tx,
+                    amount,
+                    timestamp: None,
+                    reason: None,
+                })
+                .await
+        };
+        handle(InputFileRecordType::Deposit, 1, 1, Some(20000.0))
+            .await
+            .expect("flagged deposit should be applied");
+        handle(InputFileRecordType::Deposit, 2, 2, Some(100.0))
+            .await
+            .unwrap();
+        for tx in [7, 8, 9] {
+            assert!(handle(InputFileRecordType::Dispute, 2, tx, None)
+                .await
+                .is_err());
+        }
+        let res = handle(InputFileRecordType::Deposit, 3, 3, Some(1.0)).await;
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "rejected by rule deny_clients: client is denied"
+        );
+        // locking rejects the record and does not open an account for a new client
+        assert!(handle(InputFileRecordType::Withdrawal, 4, 4, Some(20000.0))
+            .await
+            .is_err());
+
+        assert_client(&service, 1, 20000.0, 0.0, false);
+        assert_client(&service, 2, 100.0, 0.0, true);
+        assert!(service
+            .get_records()
+            .all(|record| record.client != 3 && record.client != 4));
+        let flagged = service
+            .get_flagged_records()
+            .map(|record| (record.tx, record.rule, record.action))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flagged,
+            vec![
+                (1, "large_amount".to_string(), RuleAction::Flag),
+                (9, "failed_disputes".to_string(), RuleAction::Lock),
+                (3, "deny_clients".to_string(), RuleAction::Reject),
+                (4, "large_amount".to_string(), RuleAction::Lock),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dispute_window_and_expiry() {
+        INIT.call_once(crate::logging::init);
+        let time = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+        let clock = ManualClock::new(time("2024-01-01T00:00:00Z"));
+        let config = toml::from_str(
+            r#"
+            [disputes]
+            window_days = 30
+            expire_after_days = 7
+            "#,
+        )
+        .expect("config should parse");
+        let mut service = Service::with_clock(config, Box::new(clock.clone()));
+        let record = |r#type, tx, amount, timestamp: Option<&str>| InputFileRecord {
+            r#type,
+            client: 1,
+            tx,
+            amount,
+            timestamp: timestamp.map(time),
+            reason: None,
+        };
+        service
+            .handle(&record(InputFileRecordType::Deposit, 1, Some(10.0), None))
+            .await
+            .unwrap();
+        service
+            .handle(&record(
+                InputFileRecordType::Deposit,
+                2,
+                Some(5.0),
+                Some("2024-01-20T00:00:00Z"),
+            ))
+            .await
+            .unwrap();
+
+        // the deposit without timestamp was taken at the clock time
+        clock.set(time("2024-02-15T00:00:00Z"));
+        let res = service
+            .handle(&record(InputFileRecordType::Dispute, 1, None, None))
+            .await;
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "dispute failure, dispute window expired for transaction from: 2024-01-01 00:00:00 UTC"
+        );
+        service
+            .handle(&record(InputFileRecordType::Dispute, 2, None, None))
+            .await
+            .unwrap();
+        assert_client(&service, 1, 10.0, 5.0, false);
+
+        // any later record expires the dispute that has been open for too long
+        clock.set(time("2024-02-23T00:00:00Z"));
+        service
+            .handle(&record(InputFileRecordType::Deposit, 3, Some(1.0), None))
+            .await
+            .unwrap();
+        assert_client(&service, 1, 16.0, 0.0, false);
+    }
+
+    #[tokio::test]
+    async fn replays_and_conflicts() {
+        let mut service = setup();
+        let records = [
+            (InputFileRecordType::Deposit, 1, 1, Some(10.0)),
+            (InputFileRecordType::Deposit, 1, 2, Some(5.0)),
+            (InputFileRecordType::Dispute, 1, 1, None),
+        ];
+        for (r#type, client, tx, amount) in records.iter().chain(records.iter()).copied() {
+            let _ = service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client,
+                    tx,
+                    amount,
+                    timestamp: None,
+                    reason: None,
+                })
+                .await;
+        }
+        // second dispute of the same transaction is rejected, the rest is replayed
+        assert_client(&service, 1, 5.0, 10.0, false);
+
+        let res = service
+            .handle(&InputFileRecord {
+                r#type: InputFileRecordType::Deposit,
+                client: 2,
+                tx: 1,
+                amount: Some(10.0),
+                timestamp: None,
+                reason: None,
+            })
+            .await;
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "conflicting duplicate transaction id: 1"
+        );
+        assert!(service.get_records().all(|record| record.client != 2));
+        assert_eq!(
+            service.run_summary(),
+            RunSummary {
+                records: 7,
+                rejected: 2,
+                replays: 2,
+                conflicts: 1,
+            }
+        );
+        let flagged = service.get_flagged_records().collect::<Vec<_>>();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].rule, "idempotency");
+        assert_eq!(flagged[0].action, RuleAction::Flag);
+    }
+
+    #[tokio::test]
+    async fn reversals() {
+        let mut service = setup_with_config(
+            r#"
+            [fees.deposit]
+            policy = "flat"
+            amount = 1.0
+            "#,
+        );
+        let records = [
+            (InputFileRecordType::Deposit, 1, 1, Some(10.0), None),
+            (InputFileRecordType::Deposit, 1, 2, Some(20.0), None),
+            (InputFileRecordType::Withdrawal, 1, 3, Some(5.0), None),
+            (InputFileRecordType::Dispute, 1, 2, None, None),
+            (
+                InputFileRecordType::Reversal,
+                1,
+                2,
+                None,
+                Some("misapplied"),
+            ),
+            (
+                InputFileRecordType::Reversal,
+                1,
+                3,
+                None,
+                Some("duplicate payout"),
+            ),
+            // only funded once the withdrawal is reversed
+            (
+                InputFileRecordType::Reversal,
+                1,
+                1,
+                None,
+                Some("misapplied"),
+            ),
+        ];
+        for (r#type, client, tx, amount, reason) in records {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client,
+                    tx,
+                    amount,
+                    timestamp: None,
+                    reason: reason.map(str::to_string),
+                })
+                .await
+                .expect("record should succeed");
+        }
+        // both deposits and their fees are undone, including the held funds
+        assert_client(&service, 1, 0.0, 0.0, false);
+        assert_eq!(service.revenue_account().balance(), 0.0);
+
+        for (r#type, tx, reason) in [
+            (InputFileRecordType::Dispute, 1, None),
+            (InputFileRecordType::Reversal, 1, Some("again".to_string())),
+            (
+                InputFileRecordType::Reversal,
+                4,
+                Some("unknown".to_string()),
+            ),
+        ] {
+            let res = service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client: 1,
+                    tx,
+                    amount: None,
+                    timestamp: None,
+                    reason,
+                })
+                .await;
+            assert!(res.is_err());
+        }
+
+        let history = service.get_history_records().collect::<Vec<_>>();
+        assert_eq!(history.len(), 3);
+        assert!(history
+            .iter()
+            .all(|record| record.status == TransactionStatus::Reversed));
+        assert_eq!(history[2].reason.as_deref(), Some("duplicate payout"));
+    }
+
+    #[tokio::test]
+    async fn reversal_of_spent_deposit() {
+        INIT.call_once(crate::logging::init);
+        let mut config = Config::default();
+        config.clients.insert(
+            ClientId::new(2),
+            ClientConfig {
+                credit_limit: 10.0,
+                ..Default::default()
+            },
+        );
+        config.invariants.check = InvariantCheck::PerTransaction;
+        let mut service = Service::with_config(config);
+        let record = |r#type, client, tx, amount| InputFileRecord {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            reason: (r#type == InputFileRecordType::Reversal).then(|| "misapplied".to_string()),
+        };
+        for client in [1, 2] {
+            for (r#type, tx, amount) in [
+                (InputFileRecordType::Deposit, 1, Some(10.0)),
+                (InputFileRecordType::Withdrawal, 2, Some(8.0)),
+            ] {
+                service
+                    .handle(&record(r#type, client, client * 10 + tx, amount))
+                    .await
+                    .expect("record should succeed");
+            }
+        }
+        // taking the deposit back would leave client 1 at -8 without a credit limit
+        let res = service
+            .handle(&record(InputFileRecordType::Reversal, 1, 11, None))
+            .await;
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .starts_with("reversal failure, not enough funds"));
+        assert_client(&service, 1, 2.0, 0.0, false);
+        service
+            .handle(&record(InputFileRecordType::Reversal, 2, 21, None))
+            .await
+            .expect("reversal within the credit limit should succeed");
+        assert_client(&service, 2, -8.0, 0.0, false);
+        assert_eq!(service.invariant_violations().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn reversal_of_resolved_deposit() {
+        let mut service = setup();
+        for (r#type, tx, amount, reason) in [
+            (InputFileRecordType::Deposit, 1, Some(10.0), None),
+            (InputFileRecordType::Deposit, 2, Some(5.0), None),
+            (InputFileRecordType::Dispute, 1, None, None),
+            (InputFileRecordType::Resolve, 1, None, None),
+            (
+                InputFileRecordType::Reversal,
+                1,
+                None,
+                Some("misapplied".to_string()),
+            ),
+        ] {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client: 1,
+                    tx,
+                    amount,
+                    timestamp: None,
+                    reason,
+                })
+                .await
+                .expect("record should succeed");
+        }
+        assert_client(&service, 1, 5.0, 0.0, false);
+        // the reversal took the deposit out of chargeback eligibility
+        let res = service
+            .handle(&InputFileRecord {
+                r#type: InputFileRecordType::Chargeback,
+                client: 1,
+                tx: 1,
+                amount: None,
+                timestamp: None,
+                reason: None,
+            })
+            .await;
+        assert!(res.is_err());
+        assert_client(&service, 1, 5.0, 0.0, false);
+    }
+
+    #[tokio::test]
+    async fn authorizations() {
+        let mut service = setup_with_config(
+            r#"
+            [authorizations]
+            expire_after_hours = 24
+            "#,
+        );
+        let records = [
+            (
+                InputFileRecordType::Deposit,
+                1,
+                Some(100.0),
+                "2024-01-01T00:00:00Z",
+            ),
+            (
+                InputFileRecordType::Authorize,
+                2,
+                Some(30.0),
+                "2024-01-01T01:00:00Z",
+            ),
+            (
+                InputFileRecordType::Authorize,
+                3,
+                Some(20.0),
+                "2024-01-01T02:00:00Z",
+            ),
+            (
+                InputFileRecordType::Authorize,
+                4,
+                Some(10.0),
+                "2024-01-01T03:00:00Z",
+            ),
+            (
+                InputFileRecordType::Dispute,
+                1,
+                None,
+                "2024-01-01T04:00:00Z",
+            ),
+        ];
+        let record = |r#type, tx, amount, timestamp: &str| InputFileRecord {
+            r#type,
+            client: 1,
+            tx,
+            amount,
+            timestamp: Some(timestamp.parse().unwrap()),
+            reason: None,
+        };
+        for (r#type, tx, amount, timestamp) in records {
+            let _ = service.handle(&record(r#type, tx, amount, timestamp)).await;
+        }
+        // the dispute fails, only 40 are left available
+        assert_client(&service, 1, 40.0, 60.0, false);
+        let res = service
+            .handle(&record(
+                InputFileRecordType::Authorize,
+                5,
+                Some(50.0),
+                "2024-01-01T05:00:00Z",
+            ))
+            .await;
+        assert!(res.is_err());
+
+        let records = [
+            (
+                InputFileRecordType::Capture,
+                2,
+                Some(25.0),
+                "2024-01-01T06:00:00Z",
+            ),
+            (InputFileRecordType::Void, 3, None, "2024-01-01T07:00:00Z"),
+        ];
+        for (r#type, tx, amount, timestamp) in records {
+            service
+                .handle(&record(r#type, tx, amount, timestamp))
+                .await
+                .expect("record should succeed");
+        }
+        assert_client(&service, 1, 65.0, 10.0, false);
+        for (r#type, tx, amount) in [
+            (InputFileRecordType::Capture, 2, None),
+            (InputFileRecordType::Void, 1, None),
+            (InputFileRecordType::Capture, 4, Some(11.0)),
+        ] {
+            let res = service
+                .handle(&record(r#type, tx, amount, "2024-01-01T08:00:00Z"))
+                .await;
+            assert!(res.is_err());
+        }
+
+        // the remaining authorization expires with the next record
+        service
+            .handle(&record(
+                InputFileRecordType::Deposit,
+                6,
+                Some(1.0),
+                "2024-01-02T04:00:00Z",
+            ))
+            .await
+            .unwrap();
+        assert_client(&service, 1, 76.0, 0.0, false);
+        let statuses = service
+            .get_history_records()
+            .map(|record| (record.tx, record.status))
+            .collect::<Vec<_>>();
+        assert!(statuses.contains(&(2, TransactionStatus::Captured)));
+        assert!(statuses.contains(&(3, TransactionStatus::Voided)));
+        assert!(statuses.contains(&(4, TransactionStatus::Expired)));
+        assert!(statuses.contains(&(5, TransactionStatus::Failure)));
+    }
+
+    #[tokio::test]
+    async fn scheduled_instructions() {
+        let mut service = setup_with_config(
+            r#"
+            [schedule]
+            max_retries = 1
+            retry_after_days = 2
+            "#,
+        );
+        service
+            .handle(&InputFileRecord {
+                r#type: InputFileRecordType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(25.0),
+                timestamp: Some("2024-01-01T00:00:00Z".parse().unwrap()),
+                reason: None,
+            })
+            .await
+            .unwrap();
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+        let instructions = [
+            ScheduledInstruction {
+                id: 1,
+                r#type: InputFileRecordType::Withdrawal,
+                client: 1,
+                amount: 10.0,
+                start: date("2024-01-31"),
+                recurrence: Some(Recurrence::Monthly),
+                end: None,
+            },
+            ScheduledInstruction {
+                id: 2,
+                r#type: InputFileRecordType::Deposit,
+                client: 1,
+                amount: 6.0,
+                start: date("2024-04-01"),
+                recurrence: None,
+                end: None,
+            },
+        ];
+        assert_eq!(
+            service
+                .run_schedule(&instructions, date("2024-02-29"))
+                .await,
+            2
+        );
+        assert_client(&service, 1, 5.0, 0.0, false);
+
+        // the march order fails, its retry succeeds after the one-off deposit
+        assert_eq!(
+            service
+                .run_schedule(&instructions, date("2024-04-30"))
+                .await,
+            4
+        );
+        assert_client(&service, 1, 1.0, 0.0, false);
+        let outcomes = service
+            .get_scheduled_records()
+            .map(|record| (record.instruction, record.date, record.outcome))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes[2..],
+            [
+                (1, date("2024-03-31"), AttemptOutcome::Retrying),
+                (2, date("2024-04-01"), AttemptOutcome::Applied),
+                (1, date("2024-04-02"), AttemptOutcome::Applied),
+                (1, date("2024-04-30"), AttemptOutcome::Retrying),
+            ]
+        );
+        // the pending retry is kept for the next run
+        assert_eq!(service.export_snapshot().schedule[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn invariants_hold_per_transaction() {
+        let config = r#"
+            [invariants]
+            check = "per-transaction"
+
+            [fees.deposit]
+            policy = "flat"
+            amount = 0.5
+
+            [fees.withdrawal]
+            policy = "percentage"
+            rate = 0.01
+            "#;
+        let mut service = setup_with_config(config);
+        let records = [
+            (InputFileRecordType::Deposit, 1, 1, Some(100.0), None),
+            (InputFileRecordType::Deposit, 1, 2, Some(50.0), None),
+            (InputFileRecordType::Withdrawal, 1, 3, Some(20.0), None),
+            (InputFileRecordType::Withdrawal, 1, 4, Some(1000.0), None),
+            (InputFileRecordType::Dispute, 1, 1, None, None),
+            (InputFileRecordType::Resolve, 1, 1, None, None),
+            (InputFileRecordType::Authorize, 1, 5, Some(30.0), None),
+            (InputFileRecordType::Capture, 1, 5, Some(10.0), None),
+            (
+                InputFileRecordType::Reversal,
+                1,
+                3,
+                None,
+                Some("wrong payout"),
+            ),
+            (InputFileRecordType::Dispute, 1, 2, None, None),
+            (InputFileRecordType::Deposit, 2, 6, Some(10.0), None),
+            (InputFileRecordType::Dispute, 2, 6, None, None),
+            (InputFileRecordType::Resolve, 2, 6, None, None),
+            (InputFileRecordType::Chargeback, 2, 6, None, None),
+        ];
+        for (r#type, client, tx, amount, reason) in records {
+            let _ = service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client,
+                    tx,
+                    amount,
+                    timestamp: None,
+                    reason: reason.map(str::to_string),
+                })
+                .await;
+        }
+        service
+            .accrue_interest("2024-01-01".parse().unwrap())
+            .unwrap();
+        service.check_invariants("end of run");
+        assert_eq!(service.invariant_violations().count(), 0);
+
+        // the opening balance of an imported state is taken into account
+        let mut restored = setup_with_config(config);
+        restored.import_snapshot(service.export_snapshot());
+        restored
+            .handle(&InputFileRecord {
+                r#type: InputFileRecordType::Withdrawal,
+                client: 1,
+                tx: 7,
+                amount: Some(5.0),
+                timestamp: None,
+                reason: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(restored.invariant_violations().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn snapshot_round_trip() {
+        INIT.call_once(crate::logging::init);
+        let config: Config = toml::from_str(
+            r#"
+            [fees.deposit]
+            policy = "flat"
+            amount = 0.5
+
+            [limits]
+            max_withdrawals = 1
+            "#,
+        )
+        .expect("config should parse");
+        let record = |r#type, client, tx, amount| InputFileRecord {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp: Some("2024-01-01T00:00:00Z".parse().unwrap()),
+            reason: None,
+        };
+        let mut original = Service::with_config(config.clone());
+        for (r#type, client, tx, amount) in [
+            (InputFileRecordType::Deposit, 1, 1, Some(10.0)),
+            (InputFileRecordType::Deposit, 1, 2, Some(20.0)),
+            (InputFileRecordType::Deposit, 2, 3, Some(5.0)),
+            (InputFileRecordType::Withdrawal, 2, 4, Some(50.0)),
+            (InputFileRecordType::Dispute, 1, 1, None),
+            (InputFileRecordType::Dispute, 2, 3, None),
+            (InputFileRecordType::Resolve, 2, 3, None),
+            (InputFileRecordType::Withdrawal, 1, 7, Some(3.0)),
+        ] {
+            let _ = original.handle(&record(r#type, client, tx, amount)).await;
+        }
+
+        let path = std::env::temp_dir().join(format!("bank-snapshot-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let snapshot = original.export_snapshot();
+        snapshot.save(path).expect("snapshot should be saved");
+        let loaded = Snapshot::load(path).expect("snapshot should be loaded");
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        let mut restored = Service::with_config(config);
+        restored.import_snapshot(loaded);
+        assert_eq!(restored.export_snapshot(), snapshot);
+
+        for (r#type, client, tx, amount) in [
+            (InputFileRecordType::Deposit, 1, 1, Some(10.0)),
+            (InputFileRecordType::Deposit, 1, 2, Some(1.0)),
+            (InputFileRecordType::Dispute, 1, 1, None),
+            (InputFileRecordType::Resolve, 1, 1, None),
+            (InputFileRecordType::Chargeback, 2, 3, None),
+            (InputFileRecordType::Deposit, 2, 5, Some(1.0)),
+            (InputFileRecordType::Withdrawal, 1, 6, Some(5.0)),
+        ] {
+            let record = record(r#type, client, tx, amount);
+            let expected = original
+                .handle(&record)
+                .await
+                .map_err(|err| err.to_string());
+            let actual = restored
+                .handle(&record)
+                .await
+                .map_err(|err| err.to_string());
+            assert_eq!(actual, expected);
+        }
+        // the withdrawal before the snapshot still counts against the limit
+        let withdrawal = record(InputFileRecordType::Withdrawal, 1, 8, Some(1.0));
+        for service in [&mut original, &mut restored] {
+            assert!(service
+                .handle(&withdrawal)
+                .await
+                .unwrap_err()
+                .to_string()
+                .starts_with("withdrawal count limit exceeded"));
+        }
+        assert_eq!(restored.export_snapshot(), original.export_snapshot());
+    }
+
+    #[tokio::test]
+    async fn resolve_after_rounding_of_held_funds() {
+        let mut service = setup();
+        // 0.01 + 0.02 - 0.01 is below 0.02 in f64
+        let records = [
+            (InputFileRecordType::Deposit, 1, Some(0.01)),
+            (InputFileRecordType::Deposit, 2, Some(0.02)),
+            (InputFileRecordType::Deposit, 3, Some(0.1)),
+            (InputFileRecordType::Dispute, 1, None),
+            (InputFileRecordType::Dispute, 2, None),
+            (InputFileRecordType::Resolve, 1, None),
+            (InputFileRecordType::Resolve, 2, None),
+        ];
+        for (r#type, tx, amount) in records {
+            service
+                .handle(&InputFileRecord {
+                    r#type,
+                    client: 1,
+                    tx,
+                    amount,
+                    timestamp: None,
+                    reason: None,
+                })
+                .await
+                .expect("service failed to handle request");
+        }
+        let records = service.get_records().collect::<Vec<_>>();
+        assert_eq!(records[0].held, 0.0);
+        assert!((records[0].available - 0.13).abs() < 1e-9);
+    }
 }
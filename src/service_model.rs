@@ -0,0 +1,229 @@
+//! Property tests checking `Service` against a simple reference model of deposits, withdrawals
+//! and disputes, run with the invariant checker enabled after every record
+use crate::{
+    input_file_reader::{InputFileRecord, InputFileRecordType},
+    output_record::OutputRecordProvider,
+    service::{Service, TransactionRecordHandler},
+};
+use proptest::prelude::*;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct ModelClient {
+    available: f64,
+    held: f64,
+    locked: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModelTransaction {
+    r#type: InputFileRecordType,
+    client: u64,
+    amount: f64,
+    failed: bool,
+    disputed: bool,
+    chargeback_eligible: bool,
+}
+
+/// Mirrors the engine rules without fees, limits or rules configured
+#[derive(Debug, Default)]
+struct Model {
+    clients: HashMap<u64, ModelClient>,
+    transactions: HashMap<u64, ModelTransaction>,
+}
+
+impl Model {
+    /// Returns whether the engine is expected to accept the record
+    fn apply(&mut self, record: &InputFileRecord) -> bool {
+        let &InputFileRecord {
+            r#type,
+            client,
+            tx,
+            amount,
+            ..
+        } = record;
+        if r#type != InputFileRecordType::Resolve
+            && self.clients.get(&client).is_some_and(|info| info.locked)
+        {
+            return true;
+        }
+        match r#type {
+            InputFileRecordType::Deposit | InputFileRecordType::Withdrawal => {
+                self.transfer(r#type, client, tx, amount.expect("amount should be set"))
+            }
+            InputFileRecordType::Dispute => self.dispute(client, tx),
+            InputFileRecordType::Resolve => self.resolve(client, tx),
+            InputFileRecordType::Chargeback => self.chargeback(client, tx),
+            _ => unreachable!("only core record types are generated"),
+        }
+    }
+
+    fn transfer(&mut self, r#type: InputFileRecordType, client: u64, tx: u64, amount: f64) -> bool {
+        if let Some(known) = self.transactions.get(&tx) {
+            // identical replays are ignored, anything else reusing the id is a conflict
+            return known.r#type == r#type && known.client == client && known.amount == amount;
+        }
+        let transaction = self.transactions.entry(tx).or_insert(ModelTransaction {
+            r#type,
+            client,
+            amount,
+            failed: false,
+            disputed: false,
+            chargeback_eligible: false,
+        });
+        if amount < 0.0 {
+            transaction.failed = true;
+            return false;
+        }
+        if r#type == InputFileRecordType::Deposit {
+            self.clients.entry(client).or_default().available += amount;
+            return true;
+        }
+        let Some(info) = self.clients.get_mut(&client) else {
+            return false;
+        };
+        if info.available < amount {
+            transaction.failed = true;
+        } else {
+            info.available -= amount;
+        }
+        true
+    }
+
+    fn dispute(&mut self, client: u64, tx: u64) -> bool {
+        let (Some(transaction), Some(info)) = (
+            self.transactions.get_mut(&tx),
+            self.clients.get_mut(&client),
+        ) else {
+            return false;
+        };
+        if transaction.disputed
+            || transaction.r#type != InputFileRecordType::Deposit
+            || transaction.failed
+            || transaction.client != client
+            || info.available < transaction.amount
+        {
+            return false;
+        }
+        transaction.disputed = true;
+        info.available -= transaction.amount;
+        info.held += transaction.amount;
+        true
+    }
+
+    fn resolve(&mut self, client: u64, tx: u64) -> bool {
+        let Some(transaction) = self
+            .transactions
+            .get_mut(&tx)
+            .filter(|transaction| transaction.disputed && transaction.client == client)
+        else {
+            return false;
+        };
+        let info = self.clients.get_mut(&client).expect("client should exist");
+        transaction.disputed = false;
+        transaction.chargeback_eligible = true;
+        info.available += transaction.amount;
+        info.held -= transaction.amount;
+        true
+    }
+
+    fn chargeback(&mut self, client: u64, tx: u64) -> bool {
+        let Some(transaction) = self
+            .transactions
+            .get_mut(&tx)
+            .filter(|transaction| transaction.chargeback_eligible)
+        else {
+            return false;
+        };
+        let Some(info) = self
+            .clients
+            .get_mut(&client)
+            .filter(|_| transaction.client == client)
+        else {
+            return false;
+        };
+        if info.available < transaction.amount {
+            return false;
+        }
+        transaction.chargeback_eligible = false;
+        info.available -= transaction.amount;
+        info.locked = true;
+        true
+    }
+}
+
+fn record_strategy() -> impl Strategy<Value = InputFileRecord> {
+    // few clients and transaction ids so records often refer to foreign, missing or
+    // already disputed transactions
+    let r#type = prop_oneof![
+        3 => Just(InputFileRecordType::Deposit),
+        2 => Just(InputFileRecordType::Withdrawal),
+        2 => Just(InputFileRecordType::Dispute),
+        1 => Just(InputFileRecordType::Resolve),
+        1 => Just(InputFileRecordType::Chargeback),
+    ];
+    (r#type, 1..=3u64, 1..=12u64, -50..2_000i32).prop_map(|(r#type, client, tx, cents)| {
+        let amount = matches!(
+            r#type,
+            InputFileRecordType::Deposit | InputFileRecordType::Withdrawal
+        )
+        .then_some(cents as f64 / 100.0);
+        InputFileRecord {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            reason: None,
+        }
+    })
+}
+
+fn run(records: &[InputFileRecord]) -> Result<(), TestCaseError> {
+    let mut service = Service::with_config(
+        toml::from_str(
+            r#"
+            [invariants]
+            check = "per-transaction"
+            "#,
+        )
+        .expect("config should parse"),
+    );
+    let mut model = Model::default();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("runtime should start");
+    for (index, record) in records.iter().enumerate() {
+        let accepted = runtime.block_on(service.handle(record)).is_ok();
+        prop_assert_eq!(
+            accepted,
+            model.apply(record),
+            "outcome of record {} differs: {:?}",
+            index,
+            record
+        );
+    }
+    let violations = service.invariant_violations().collect::<Vec<_>>();
+    prop_assert!(violations.is_empty(), "{:?}", violations);
+    let clients = service.get_records().collect::<Vec<_>>();
+    prop_assert_eq!(clients.len(), model.clients.len());
+    for client in clients {
+        let expected = model.clients[&client.client];
+        prop_assert!(
+            (client.available - expected.available).abs() < 1e-6
+                && (client.held - expected.held).abs() < 1e-6
+                && client.locked == expected.locked,
+            "client {:?} differs from model {:?}",
+            client,
+            expected
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn service_matches_model(records in prop::collection::vec(record_strategy(), 0..80)) {
+        run(&records)?;
+    }
+}
@@ -0,0 +1,349 @@
+use crate::{
+    revenue_account::FeePostingStatus,
+    transaction_info::{TransactionStatus, TransactionType},
+};
+use anyhow::{bail, Context};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+};
+
+/// Bump on every change to the snapshot format and add the matching migration
+pub const SNAPSHOT_VERSION: u32 = 8;
+
+/// Upgrades a snapshot from version `index + 1` to `index + 2`
+type Migration = fn(Value) -> anyhow::Result<Value>;
+const MIGRATIONS: [Migration; SNAPSHOT_VERSION as usize - 1] = [
+    add_reversals,
+    add_authorizations,
+    add_schedule,
+    require_amounts,
+    optional_timestamps,
+    add_velocity,
+    add_balance_changes,
+];
+
+/// Version 2 records operator reversals
+fn add_reversals(mut value: Value) -> anyhow::Result<Value> {
+    let snapshot = value.as_object_mut().context("snapshot is not an object")?;
+    snapshot.insert("reversals".to_string(), Value::Array(Vec::new()));
+    snapshot.insert("version".to_string(), Value::from(2));
+    Ok(value)
+}
+
+/// Version 3 records authorization holds separately from dispute holds
+fn add_authorizations(mut value: Value) -> anyhow::Result<Value> {
+    let snapshot = value.as_object_mut().context("snapshot is not an object")?;
+    for client in snapshot
+        .get_mut("clients")
+        .and_then(Value::as_array_mut)
+        .context("snapshot missing 'clients' field")?
+    {
+        client
+            .as_object_mut()
+            .context("snapshot client is not an object")?
+            .insert("authorized".to_string(), Value::from(0.0));
+    }
+    snapshot.insert("authorizations".to_string(), Value::Array(Vec::new()));
+    snapshot.insert("version".to_string(), Value::from(3));
+    Ok(value)
+}
+
+/// Version 4 records the progress of scheduled instructions
+fn add_schedule(mut value: Value) -> anyhow::Result<Value> {
+    let snapshot = value.as_object_mut().context("snapshot is not an object")?;
+    snapshot.insert("schedule".to_string(), Value::Array(Vec::new()));
+    snapshot.insert("version".to_string(), Value::from(4));
+    Ok(value)
+}
+
+/// Version 5 requires transaction amounts. Older versions wrote non-finite amounts of failed
+/// transactions as null, those become 0
+fn require_amounts(mut value: Value) -> anyhow::Result<Value> {
+    let snapshot = value.as_object_mut().context("snapshot is not an object")?;
+    for transaction in snapshot
+        .get_mut("transactions")
+        .and_then(Value::as_array_mut)
+        .context("snapshot missing 'transactions' field")?
+    {
+        let transaction = transaction
+            .as_object_mut()
+            .context("snapshot transaction is not an object")?;
+        if transaction.get("amount").is_none_or(Value::is_null) {
+            if transaction.get("status").and_then(Value::as_str) != Some("failure") {
+                bail!(
+                    "snapshot transaction without amount is not failed: {}",
+                    transaction.get("tx").unwrap_or(&Value::Null)
+                );
+            }
+            transaction.insert("amount".to_string(), Value::from(0.0));
+        }
+    }
+    snapshot.insert("version".to_string(), Value::from(5));
+    Ok(value)
+}
+
+/// Version 6 only requires timestamps of deposits that can still be disputed, the others are
+/// dropped on load
+fn optional_timestamps(mut value: Value) -> anyhow::Result<Value> {
+    let snapshot = value.as_object_mut().context("snapshot is not an object")?;
+    snapshot.insert("version".to_string(), Value::from(6));
+    Ok(value)
+}
+
+/// Version 7 records the withdrawals counted against velocity limits
+fn add_velocity(mut value: Value) -> anyhow::Result<Value> {
+    let snapshot = value.as_object_mut().context("snapshot is not an object")?;
+    snapshot.insert("velocity".to_string(), Value::Array(Vec::new()));
+    snapshot.insert("version".to_string(), Value::from(7));
+    Ok(value)
+}
+
+/// Version 8 records the balance changes made since the last interest accrual, older snapshots
+/// accrue the gap on the balance at load time
+fn add_balance_changes(mut value: Value) -> anyhow::Result<Value> {
+    let snapshot = value.as_object_mut().context("snapshot is not an object")?;
+    snapshot.insert("balance_changes".to_string(), Value::Array(Vec::new()));
+    snapshot.insert("version".to_string(), Value::from(8));
+    Ok(value)
+}
+
+/// Engine state as stored on disk, decoupled from the in-memory tables
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub clients: Vec<ClientSnapshot>,
+    pub transactions: Vec<TransactionSnapshot>,
+    pub disputes: Vec<DisputeSnapshot>,
+    pub chargeback_eligible: Vec<u64>,
+    pub fee_postings: Vec<FeePostingSnapshot>,
+    pub reversals: Vec<ReversalSnapshot>,
+    pub authorizations: Vec<AuthorizationSnapshot>,
+    pub schedule: Vec<ScheduleSnapshot>,
+    pub velocity: Vec<VelocitySnapshot>,
+    pub balance_changes: Vec<BalanceChangeSnapshot>,
+    pub last_interest_accrual: Option<NaiveDate>,
+    pub next_system_transaction_id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ClientSnapshot {
+    pub client: u16,
+    pub available: f64,
+    pub held: f64,
+    pub authorized: f64,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TransactionSnapshot {
+    pub tx: u64,
+    pub r#type: TransactionType,
+    pub client: u16,
+    /// Optional up to version 4
+    pub amount: f64,
+    pub status: TransactionStatus,
+    /// Required up to version 5, from version 6 only kept for successful deposits
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DisputeSnapshot {
+    pub tx: u64,
+    pub client: u16,
+    pub opened_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FeePostingSnapshot {
+    pub id: u64,
+    pub origin: u64,
+    pub charged_for: TransactionType,
+    pub client: u16,
+    pub amount: f64,
+    pub status: FeePostingStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ReversalSnapshot {
+    pub tx: u64,
+    pub reason: String,
+    pub reversed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AuthorizationSnapshot {
+    pub tx: u64,
+    pub client: u16,
+    pub amount: f64,
+    pub authorized_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ScheduleSnapshot {
+    pub instruction: u64,
+    pub occurrence: u32,
+    pub attempts: u32,
+    pub retry_on: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct VelocitySnapshot {
+    pub client: u16,
+    pub withdrawn_at: DateTime<Utc>,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BalanceChangeSnapshot {
+    pub client: u16,
+    pub date: NaiveDate,
+    pub change: f64,
+}
+
+impl Snapshot {
+    pub fn load(path: &str) -> anyhow::Result<Snapshot> {
+        let file = File::open(path).context(format!("failed to open snapshot: {path}"))?;
+        let value: Value = serde_json::from_reader(BufReader::new(file))
+            .context(format!("failed to parse snapshot: {path}"))?;
+        Self::from_value(value).context(format!("failed to load snapshot: {path}"))
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let file = File::create(path).context(format!("failed to create snapshot: {path}"))?;
+        serde_json::to_writer(BufWriter::new(file), self)
+            .context(format!("failed to write snapshot: {path}"))
+    }
+
+    fn from_value(mut value: Value) -> anyhow::Result<Snapshot> {
+        let version = value
+            .get("version")
+            .and_then(Value::as_u64)
+            .context("snapshot missing 'version' field")?;
+        if version == 0 || version > SNAPSHOT_VERSION as u64 {
+            bail!("unsupported snapshot version: {version}, latest supported: {SNAPSHOT_VERSION}");
+        }
+        for migration in &MIGRATIONS[version as usize - 1..] {
+            value = migration(value)?;
+        }
+        let snapshot: Snapshot = serde_json::from_value(value)?;
+        // the dispute window of a deposit that can still be disputed is checked against its time
+        if let Some(transaction) = snapshot.transactions.iter().find(|transaction| {
+            transaction.r#type == TransactionType::Deposit
+                && transaction.status == TransactionStatus::Success
+                && transaction.timestamp.is_none()
+        }) {
+            bail!(
+                "snapshot deposit without timestamp is not settled: {}",
+                transaction.tx
+            );
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rejects_unknown_versions() {
+        for version in [0, SNAPSHOT_VERSION + 1] {
+            let res = Snapshot::from_value(json!({ "version": version }));
+            assert!(res
+                .err()
+                .unwrap()
+                .to_string()
+                .starts_with("unsupported snapshot version"));
+        }
+        assert!(Snapshot::from_value(json!({})).is_err());
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let snapshot = Snapshot::from_value(json!({
+            "version": 1,
+            "clients": [{ "client": 1, "available": 1.0, "held": 0.0, "locked": false }],
+            "transactions": [],
+            "disputes": [],
+            "chargeback_eligible": [],
+            "fee_postings": [],
+            "last_interest_accrual": null,
+            "next_system_transaction_id": 7
+        }))
+        .expect("version 1 snapshot should migrate");
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert!(snapshot.reversals.is_empty());
+        assert!(snapshot.authorizations.is_empty());
+        assert!(snapshot.schedule.is_empty());
+        assert!(snapshot.velocity.is_empty());
+        assert!(snapshot.balance_changes.is_empty());
+        assert_eq!(snapshot.clients[0].authorized, 0.0);
+        assert_eq!(snapshot.next_system_transaction_id, 7);
+    }
+
+    #[test]
+    fn migrates_null_amounts_of_version_4() {
+        let snapshot = |status: &str| {
+            json!({
+                "version": 4,
+                "clients": [],
+                "transactions": [{
+                    "tx": 1,
+                    "type": "deposit",
+                    "client": 1,
+                    "amount": null,
+                    "status": status,
+                    "timestamp": "2024-01-01T00:00:00Z"
+                }],
+                "disputes": [],
+                "chargeback_eligible": [],
+                "fee_postings": [],
+                "reversals": [],
+                "authorizations": [],
+                "schedule": [],
+                "last_interest_accrual": null,
+                "next_system_transaction_id": 7
+            })
+        };
+        let migrated = Snapshot::from_value(snapshot("failure"))
+            .expect("failed transaction without amount should migrate");
+        assert_eq!(migrated.transactions[0].amount, 0.0);
+        assert!(Snapshot::from_value(snapshot("success")).is_err());
+    }
+
+    #[test]
+    fn requires_timestamps_of_disputable_deposits() {
+        let snapshot = |r#type: &str, status: &str| {
+            json!({
+                "version": SNAPSHOT_VERSION,
+                "clients": [],
+                "transactions": [{
+                    "tx": 1,
+                    "type": r#type,
+                    "client": 1,
+                    "amount": 1.0,
+                    "status": status,
+                    "timestamp": null
+                }],
+                "disputes": [],
+                "chargeback_eligible": [],
+                "fee_postings": [],
+                "reversals": [],
+                "authorizations": [],
+                "schedule": [],
+                "velocity": [],
+                "balance_changes": [],
+                "last_interest_accrual": null,
+                "next_system_transaction_id": 7
+            })
+        };
+        assert!(Snapshot::from_value(snapshot("withdrawal", "success")).is_ok());
+        assert!(Snapshot::from_value(snapshot("deposit", "reversed")).is_ok());
+        assert!(Snapshot::from_value(snapshot("deposit", "success")).is_err());
+    }
+}
@@ -1,8 +1,9 @@
 use crate::client_id::ClientId;
+use chrono::{DateTime, Utc};
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Display, Deserialize, Copy, Clone, Serialize, Eq, PartialEq)]
+#[derive(Debug, Display, Deserialize, Copy, Clone, Serialize, Eq, PartialEq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -10,18 +11,41 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Reversal,
+    Authorize,
+    Capture,
+    Void,
 }
 
-#[derive(Debug, Display, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Display, Deserialize, Copy, Clone, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum TransactionStatus {
     Success,
     Failure,
+    /// Undone by an operator reversal
+    Reversed,
+    /// Authorization captured, any remainder released
+    Captured,
+    /// Authorization released by a void
+    Voided,
+    /// Authorization released after it expired
+    Expired,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct TransactionInfo {
     pub r#type: TransactionType,
     pub client: ClientId,
-    pub amount: Option<f64>,
+    pub amount: f64,
     pub status: TransactionStatus,
+    /// Record timestamp, or the processing time when the record had none.
+    /// Only kept while the transaction is a deposit that can still be disputed
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl TransactionInfo {
+    /// Only successful deposits can be disputed, and only they keep their timestamp
+    pub fn is_disputable(&self) -> bool {
+        self.r#type == TransactionType::Deposit && self.status == TransactionStatus::Success
+    }
 }
@@ -0,0 +1,310 @@
+use crate::{
+    client_id::ClientId,
+    transaction_id::TransactionId,
+    transaction_info::{TransactionInfo, TransactionStatus, TransactionType},
+};
+use chrono::{DateTime, Utc};
+use rustc_hash::FxHashMap;
+
+/// The dense part may always grow to this many slots
+const DENSE_MIN_SLOTS: u64 = 1 << 16;
+
+/// Timestamp position of a slot that keeps no timestamp
+const NO_TIMESTAMP: u32 = u32::MAX;
+
+/// Replay fingerprint kept for every transaction: what a record reusing its id is compared
+/// against, and what reversals and the history report read. 16 bytes
+#[derive(Debug, Copy, Clone)]
+struct Slot {
+    amount: f64,
+    client: ClientId,
+    r#type: TransactionType,
+    status: TransactionStatus,
+    /// Position in `timestamps` of a deposit that can still be disputed, `NO_TIMESTAMP` otherwise
+    timestamp: u32,
+}
+
+/// Stored transactions. Input files number transactions mostly sequentially, so ids close to the
+/// ones seen so far index a vector directly; ids the vector would have to grow too much for,
+/// such as system generated ones counting down from `u64::MAX`, go to a hash map.
+/// Every transaction keeps its fingerprint, only deposits that can still be disputed also keep
+/// their timestamp, which the dispute window is checked against
+#[derive(Debug, Default)]
+pub struct TransactionStore {
+    dense: Vec<Option<Slot>>,
+    /// Filled slots of `dense`
+    dense_len: usize,
+    sparse: FxHashMap<TransactionId, Slot>,
+    len: usize,
+    timestamps: Vec<DateTime<Utc>>,
+    /// Positions in `timestamps` released by deposits that can no longer be disputed
+    free_timestamps: Vec<u32>,
+}
+
+impl TransactionStore {
+    /// Past the first slots, the dense part only grows while at least half of it stays filled,
+    /// so strided ids cannot make it much larger than the transactions it holds
+    fn dense_may_reach(&self, id: u64) -> bool {
+        let slots = id.saturating_add(1);
+        slots <= DENSE_MIN_SLOTS || slots <= (self.dense_len as u64 + 1) * 2
+    }
+
+    fn dense_index(&self, transaction_id: &TransactionId) -> Option<usize> {
+        let index = usize::try_from(transaction_id.value()).ok()?;
+        (index < self.dense.len()).then_some(index)
+    }
+
+    fn slot(&self, transaction_id: &TransactionId) -> Option<&Slot> {
+        if let Some(slot) = self
+            .dense_index(transaction_id)
+            .and_then(|index| self.dense[index].as_ref())
+        {
+            return Some(slot);
+        }
+        self.sparse.get(transaction_id)
+    }
+
+    fn slot_mut(&mut self, transaction_id: &TransactionId) -> Option<&mut Slot> {
+        match self.dense_index(transaction_id) {
+            Some(index) if self.dense[index].is_some() => self.dense[index].as_mut(),
+            _ => self.sparse.get_mut(transaction_id),
+        }
+    }
+
+    fn info(&self, slot: &Slot) -> TransactionInfo {
+        TransactionInfo {
+            r#type: slot.r#type,
+            client: slot.client,
+            amount: slot.amount,
+            status: slot.status,
+            timestamp: (slot.timestamp != NO_TIMESTAMP)
+                .then(|| self.timestamps[slot.timestamp as usize]),
+        }
+    }
+
+    // improvement: grow the positions to u64 once a run holds billions of open deposits
+    fn keep_timestamp(&mut self, timestamp: DateTime<Utc>) -> u32 {
+        if let Some(position) = self.free_timestamps.pop() {
+            self.timestamps[position as usize] = timestamp;
+            return position;
+        }
+        let position = u32::try_from(self.timestamps.len())
+            .ok()
+            .filter(|position| *position != NO_TIMESTAMP)
+            .expect("too many disputable deposits");
+        self.timestamps.push(timestamp);
+        position
+    }
+
+    fn release_timestamp(&mut self, slot: Option<Slot>) {
+        if let Some(slot) = slot.filter(|slot| slot.timestamp != NO_TIMESTAMP) {
+            self.free_timestamps.push(slot.timestamp);
+        }
+    }
+
+    pub fn get(&self, transaction_id: &TransactionId) -> Option<TransactionInfo> {
+        self.slot(transaction_id).map(|slot| self.info(slot))
+    }
+
+    pub fn contains_key(&self, transaction_id: &TransactionId) -> bool {
+        self.slot(transaction_id).is_some()
+    }
+
+    /// Stores the transaction, its timestamp is dropped unless it can be disputed
+    pub fn insert(&mut self, transaction_id: TransactionId, info: TransactionInfo) {
+        let timestamp = match info.timestamp {
+            Some(timestamp) if info.is_disputable() => self.keep_timestamp(timestamp),
+            _ => NO_TIMESTAMP,
+        };
+        let slot = Slot {
+            amount: info.amount,
+            client: info.client,
+            r#type: info.r#type,
+            status: info.status,
+            timestamp,
+        };
+        let id = transaction_id.value();
+        if id >= self.dense.len() as u64 {
+            if !self.dense_may_reach(id) {
+                let replaced = self.sparse.insert(transaction_id, slot);
+                if replaced.is_none() {
+                    self.len += 1;
+                }
+                self.release_timestamp(replaced);
+                return;
+            }
+            self.dense.resize(id as usize + 1, None);
+        }
+        let replaced = self.dense[id as usize].replace(slot);
+        if replaced.is_some() {
+            self.release_timestamp(replaced);
+            return;
+        }
+        self.dense_len += 1;
+        // the id may have gone to the sparse part before the dense part reached it
+        let moved = if self.sparse.is_empty() {
+            None
+        } else {
+            self.sparse.remove(&transaction_id)
+        };
+        if moved.is_none() {
+            self.len += 1;
+        }
+        self.release_timestamp(moved);
+    }
+
+    /// Updates the status, a deposit that can no longer be disputed drops its timestamp
+    pub fn set_status(&mut self, transaction_id: &TransactionId, status: TransactionStatus) {
+        let Some(slot) = self.slot_mut(transaction_id) else {
+            return;
+        };
+        slot.status = status;
+        if status == TransactionStatus::Success || slot.timestamp == NO_TIMESTAMP {
+            return;
+        }
+        let position = std::mem::replace(&mut slot.timestamp, NO_TIMESTAMP);
+        self.free_timestamps.push(position);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Dense ids in order, then sparse ones in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (TransactionId, TransactionInfo)> + '_ {
+        self.dense
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| Some((TransactionId::new(index as u64), slot.as_ref()?)))
+            .chain(self.sparse.iter().map(|(id, slot)| (*id, slot)))
+            .map(|(id, slot)| (id, self.info(slot)))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = TransactionInfo> + '_ {
+        self.iter().map(|(_, info)| info)
+    }
+}
+
+impl FromIterator<(TransactionId, TransactionInfo)> for TransactionStore {
+    fn from_iter<I: IntoIterator<Item = (TransactionId, TransactionInfo)>>(iter: I) -> Self {
+        let mut store = TransactionStore::default();
+        for (transaction_id, info) in iter {
+            store.insert(transaction_id, info);
+        }
+        store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(amount: f64) -> TransactionInfo {
+        TransactionInfo {
+            r#type: TransactionType::Deposit,
+            client: ClientId::new(1),
+            amount,
+            status: TransactionStatus::Success,
+            timestamp: Some(Utc::now()),
+        }
+    }
+
+    #[test]
+    fn dense_and_sparse_ids() {
+        let mut store = TransactionStore::default();
+        let far = TransactionId::new(DENSE_MIN_SLOTS + 10);
+        let system = TransactionId::new(u64::MAX);
+        store.insert(TransactionId::new(1), info(1.0));
+        store.insert(far, info(2.0));
+        store.insert(system, info(3.0));
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.sparse.len(), 2);
+        assert_eq!(store.get(&far).map(|info| info.amount), Some(2.0));
+        assert!(!store.contains_key(&TransactionId::new(2)));
+
+        // once half of it is filled, the dense part grows past the id kept in the sparse part
+        for id in 2..DENSE_MIN_SLOTS / 2 + 20 {
+            store.insert(TransactionId::new(id), info(4.0));
+        }
+        store.insert(TransactionId::new(DENSE_MIN_SLOTS + 20), info(4.0));
+        assert_eq!(store.dense.len() as u64, DENSE_MIN_SLOTS + 21);
+        store.set_status(&far, TransactionStatus::Failure);
+        assert_eq!(
+            store.get(&far).map(|info| info.status),
+            Some(TransactionStatus::Failure)
+        );
+        store.insert(far, info(5.0));
+        assert_eq!(store.len() as u64, DENSE_MIN_SLOTS / 2 + 22);
+        assert_eq!(store.sparse.len(), 1);
+        assert_eq!(store.get(&far).map(|info| info.amount), Some(5.0));
+        assert_eq!(
+            store
+                .iter()
+                .map(|(id, _)| id.value())
+                .filter(|id| *id > DENSE_MIN_SLOTS)
+                .collect::<Vec<_>>(),
+            vec![DENSE_MIN_SLOTS + 10, DENSE_MIN_SLOTS + 20, u64::MAX]
+        );
+    }
+
+    #[test]
+    fn only_disputable_deposits_keep_timestamps() {
+        let timestamp = |store: &TransactionStore, id| {
+            store
+                .get(&TransactionId::new(id))
+                .and_then(|info| info.timestamp)
+        };
+        let mut store = TransactionStore::default();
+        let withdrawal = TransactionInfo {
+            r#type: TransactionType::Withdrawal,
+            ..info(1.0)
+        };
+        store.insert(TransactionId::new(1), info(1.0));
+        store.insert(TransactionId::new(2), withdrawal);
+        store.insert(TransactionId::new(3), info(2.0));
+        assert!(timestamp(&store, 1).is_some());
+        assert!(timestamp(&store, 2).is_none());
+        assert_eq!(store.timestamps.len(), 2);
+
+        // a reversed deposit can no longer be disputed, a new one takes its position
+        store.set_status(&TransactionId::new(1), TransactionStatus::Reversed);
+        let reversed = store.get(&TransactionId::new(1)).unwrap();
+        assert_eq!(reversed.status, TransactionStatus::Reversed);
+        assert_eq!(reversed.amount, 1.0);
+        assert!(reversed.timestamp.is_none());
+        store.insert(TransactionId::new(4), info(3.0));
+        assert_eq!(store.timestamps.len(), 2);
+        assert!(timestamp(&store, 3).is_some());
+        assert!(timestamp(&store, 4).is_some());
+    }
+
+    #[test]
+    fn slots_take_16_bytes() {
+        assert_eq!(std::mem::size_of::<Option<Slot>>(), 16);
+    }
+
+    #[test]
+    fn strided_ids_do_not_grow_the_dense_part() {
+        let slot_size = std::mem::size_of::<Option<Slot>>();
+        let mut store = TransactionStore::default();
+        for id in (0..10_000).map(|n| n * 60_000) {
+            store.insert(TransactionId::new(id), info(1.0));
+        }
+        assert_eq!(store.len(), 10_000);
+        // growing the vector up to each id would take 600 million slots, nearly ten gigabytes
+        let allocated = store.dense.capacity() * slot_size
+            + store.sparse.capacity() * std::mem::size_of::<(TransactionId, Slot)>();
+        assert!(allocated <= 4 << 20, "allocated {allocated} bytes");
+
+        let mut store = TransactionStore::default();
+        for id in 1..=200_000 {
+            store.insert(TransactionId::new(id), info(1.0));
+        }
+        assert!(store.sparse.is_empty());
+        assert!(store.dense.capacity() <= 2 * store.dense.len());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    input_file_reader::{InputFileRecord, InputFileRecordType},
+    output_record::BatchErrorRecord,
+    service::Service,
+};
+use std::fmt;
+
+/// What a transactions file would do to the engine state
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub records: usize,
+    pub accepted: usize,
+    pub rejected: Vec<BatchErrorRecord>,
+    /// Transactions that would be disputed
+    pub disputed: Vec<u64>,
+    /// Clients that would be locked, with the line of the record locking them
+    pub locked: Vec<(u64, u64)>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.rejected.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "records: {}, accepted: {}, rejected: {}, disputed: {}, locked: {}",
+            self.records,
+            self.accepted,
+            self.rejected.len(),
+            self.disputed.len(),
+            self.locked.len()
+        )?;
+        for rejected in &self.rejected {
+            let tx = rejected.tx.map_or("-".to_string(), |tx| tx.to_string());
+            writeln!(
+                f,
+                "rejected line {}, tx {tx}: {}",
+                rejected.line, rejected.error
+            )?;
+        }
+        for tx in &self.disputed {
+            writeln!(f, "disputed tx {tx}")?;
+        }
+        for (client, line) in &self.locked {
+            writeln!(f, "locked client {client} at line {line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the records against `service`, which is expected to be a throwaway copy of the
+/// engine state. Records are checked like in batch mode
+pub async fn validate(
+    service: &mut Service,
+    records: Vec<(u64, anyhow::Result<InputFileRecord>)>,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    for (line, record) in records {
+        report.records += 1;
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                report.rejected.push(BatchErrorRecord {
+                    line,
+                    tx: None,
+                    error: format!("invalid record: {err}"),
+                });
+                continue;
+            }
+        };
+        let was_locked = service.is_locked(record.client);
+        match service.handle_strict(&record).await {
+            Ok(()) => {
+                report.accepted += 1;
+                if record.r#type == InputFileRecordType::Dispute {
+                    report.disputed.push(record.tx);
+                }
+            }
+            Err(err) => report.rejected.push(BatchErrorRecord {
+                line,
+                tx: Some(record.tx),
+                error: err.to_string(),
+            }),
+        }
+        if !was_locked && service.is_locked(record.client) {
+            report.locked.push((record.client, line));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input_file_reader::InputFileReader, output_record::OutputRecordProvider,
+        service::TransactionRecordHandler, snapshot::Snapshot,
+    };
+
+    #[tokio::test]
+    async fn reports_what_the_records_would_do() {
+        let records = InputFileReader::new("tests/assets/transactions.csv".to_string())
+            .read_file_strict()
+            .expect("file should be readable");
+        let mut copy = Service::new();
+        let report = validate(&mut copy, records).await;
+        assert!(report.is_clean());
+        assert_eq!((report.records, report.accepted), (9, 9));
+        assert_eq!(report.disputed, vec![3]);
+        assert_eq!(report.locked, vec![(1, 10)]);
+
+        let records = InputFileReader::new("tests/assets/batch_invalid.csv".to_string())
+            .read_file_strict()
+            .expect("file should be readable");
+        let mut service = Service::new();
+        let report = validate(&mut service, records).await;
+        assert!(!report.is_clean());
+        assert_eq!(report.accepted, 2);
+        assert!(report
+            .to_string()
+            .contains("rejected line 4, tx 3: transaction not applied: 3, status: Failure"));
+        assert_eq!(service.get_records().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn leaves_the_snapshot_and_its_service_unchanged() {
+        let mut original = Service::new();
+        for (_, record) in InputFileReader::new("tests/assets/transactions.csv".to_string())
+            .read_file_strict()
+            .expect("file should be readable")
+        {
+            let _ = original.handle(&record.unwrap()).await;
+        }
+        let path = std::env::temp_dir().join(format!("validate-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let before = original.export_snapshot();
+        before.save(path).expect("snapshot should be saved");
+        let saved = std::fs::read(path).unwrap();
+
+        // the copy validate runs against, built like the validate command does
+        let mut copy = Service::new();
+        copy.import_snapshot(Snapshot::load(path).expect("snapshot should be loaded"));
+        let records = vec![(
+            2,
+            Ok(InputFileRecord {
+                r#type: InputFileRecordType::Deposit,
+                client: 3,
+                tx: 100,
+                amount: Some(5.0),
+                timestamp: None,
+                reason: None,
+            }),
+        )];
+        let report = validate(&mut copy, records).await;
+        assert_eq!(report.accepted, 1);
+        assert_ne!(copy.export_snapshot(), before);
+
+        assert_eq!(std::fs::read(path).unwrap(), saved);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(original.export_snapshot(), before);
+    }
+}